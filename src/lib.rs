@@ -1 +1,34 @@
+use http::Request;
+use opentelemetry::trace::SpanContext;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
 pub mod trace;
+mod util;
+
+/// Returns the OpenTelemetry [`Context`] of the request.
+///
+/// The middlewares store the [`Context`] of the request [`Span`] in the request extensions, since
+/// the [`Span`] is entered only while the inner service is polled, the work spawned on another
+/// task loses the trace linkage. The returned [`Context`] can be attached to the spawned future
+/// using [`FutureExt::with_context`]. If the request has not been handled by a middleware the
+/// current [`Context`] is returned.
+///
+/// [`Context`]: opentelemetry::Context
+/// [`Span`]: tracing::Span
+/// [`FutureExt::with_context`]: opentelemetry::trace::FutureExt::with_context
+pub fn current_context_extension<B>(request: &Request<B>) -> opentelemetry::Context {
+    request
+        .extensions()
+        .get::<opentelemetry::Context>()
+        .cloned()
+        .unwrap_or_else(opentelemetry::Context::current)
+}
+
+/// Adds a link from the [`Span`] to the given [`SpanContext`].
+///
+/// Links relate spans that are not in a parent-child relationship, e.g. the request that consumes
+/// a batch of messages and the requests that produced them.
+pub fn add_link(span: &Span, span_context: SpanContext) {
+    span.add_link(span_context);
+}
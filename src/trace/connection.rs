@@ -0,0 +1,216 @@
+//! Middleware that adds tracing to the connections accepted by a server.
+//!
+//! The [`ConnectionLayer`] wraps the service that creates a new [`Service`] for each accepted
+//! connection (the `MakeService`), a [`Span`] is created when the connection is accepted and it
+//! is closed when the service of the connection is dropped. The [`Span`] is inserted in the
+//! extensions of each request received on the connection as [`ConnectionSpan`], the
+//! [`HttpLayer`] and the [`GrpcLayer`] link their request [`Span`]s to it, so that all the
+//! requests sent on the same connection (e.g. HTTP/1 keep-alive or HTTP/2 multiplexing) can be
+//! grouped.
+//!
+//! The peer of the connection is read from the target passed to the `MakeService`, which must
+//! implement [`ConnectionInfo`], and it is recorded as `network.peer.address`,
+//! `network.peer.port` and `network.transport`.
+//!
+//! [`GrpcLayer`]: super::GrpcLayer
+//! [`HttpLayer`]: super::HttpLayer
+
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use http::Request;
+use pin_project::pin_project;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::{Level, Span};
+
+use super::extensions::{ConnectionSpan, NetworkTransport};
+
+/// Information about the peer of a connection, read from the target of the `MakeService`.
+pub trait ConnectionInfo {
+    /// Address of the peer, e.g. the IP address or the path of the Unix domain socket.
+    fn peer_address(&self) -> Option<String>;
+
+    /// Port of the peer, if the transport has ports.
+    fn peer_port(&self) -> Option<u16> {
+        None
+    }
+
+    /// Transport of the connection.
+    fn network_transport(&self) -> NetworkTransport;
+}
+
+impl ConnectionInfo for SocketAddr {
+    fn peer_address(&self) -> Option<String> {
+        Some(self.ip().to_string())
+    }
+
+    fn peer_port(&self) -> Option<u16> {
+        Some(self.port())
+    }
+
+    fn network_transport(&self) -> NetworkTransport {
+        NetworkTransport::Tcp
+    }
+}
+
+impl<T: ConnectionInfo + ?Sized> ConnectionInfo for &T {
+    fn peer_address(&self) -> Option<String> {
+        (**self).peer_address()
+    }
+
+    fn peer_port(&self) -> Option<u16> {
+        (**self).peer_port()
+    }
+
+    fn network_transport(&self) -> NetworkTransport {
+        (**self).network_transport()
+    }
+}
+
+/// [`Layer`] that adds tracing to the connections accepted by a server.
+#[derive(Clone, Debug)]
+pub struct ConnectionLayer {
+    level: Level,
+}
+
+impl ConnectionLayer {
+    /// [`Span`]s are constructed at the given level.
+    pub fn new(level: Level) -> Self {
+        Self { level }
+    }
+}
+
+impl<M> Layer<M> for ConnectionLayer {
+    type Service = MakeConnection<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeConnection {
+            inner,
+            level: self.level,
+        }
+    }
+}
+
+/// Middleware that adds tracing to the services created for each connection.
+#[derive(Clone, Debug)]
+pub struct MakeConnection<M> {
+    inner: M,
+    level: Level,
+}
+
+impl<M, T> Service<T> for MakeConnection<M>
+where
+    M: Service<T>,
+    T: ConnectionInfo,
+{
+    type Response = Connection<M::Response>;
+    type Error = M::Error;
+    type Future = MakeConnectionFuture<M::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let span = make_connection_span(self.level, &target);
+        let inner = self.inner.call(target);
+
+        MakeConnectionFuture {
+            inner,
+            span: Some(span),
+        }
+    }
+}
+
+/// Response future for [`MakeConnection`].
+#[pin_project]
+pub struct MakeConnectionFuture<F> {
+    #[pin]
+    inner: F,
+    span: Option<Span>,
+}
+
+impl<F, S, E> Future for MakeConnectionFuture<F>
+where
+    F: Future<Output = Result<S, E>>,
+{
+    type Output = Result<Connection<S>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let inner = ready!(this.inner.poll(cx))?;
+        let span = this.span.take().unwrap_or_else(Span::none);
+
+        Poll::Ready(Ok(Connection { inner, span }))
+    }
+}
+
+/// Middleware that inserts the connection [`Span`] in the extensions of each request.
+#[derive(Clone, Debug)]
+pub struct Connection<S> {
+    inner: S,
+    span: Span,
+}
+
+impl<S, B> Service<Request<B>> for Connection<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        req.extensions_mut()
+            .insert(ConnectionSpan(self.span.clone()));
+        self.inner.call(req)
+    }
+}
+
+/// Creates a new [`Span`] for the connection accepted from the given target.
+fn make_connection_span<T: ConnectionInfo>(level: Level, target: &T) -> Span {
+    macro_rules! make_span {
+        ($level:expr) => {{
+            use tracing::field::Empty;
+
+            tracing::span!(
+                target: "tower_otel::trace::connection",
+                $level,
+                "CONNECTION",
+                "otel.kind" = "internal",
+                "network.peer.address" = Empty,
+                "network.peer.port" = Empty,
+                "network.transport" = Empty,
+            )
+        }};
+    }
+
+    let span = match level {
+        Level::ERROR => make_span!(Level::ERROR),
+        Level::WARN => make_span!(Level::WARN),
+        Level::INFO => make_span!(Level::INFO),
+        Level::DEBUG => make_span!(Level::DEBUG),
+        Level::TRACE => make_span!(Level::TRACE),
+    };
+
+    if !span.is_disabled() {
+        if let Some(peer_address) = target.peer_address() {
+            span.record("network.peer.address", peer_address);
+        }
+        if let Some(peer_port) = target.peer_port() {
+            span.record("network.peer.port", peer_port as i64);
+        }
+        span.record("network.transport", target.network_transport().as_str());
+    }
+
+    span
+}
@@ -0,0 +1,203 @@
+//! Extensions read or written by the middlewares.
+//!
+//! Unless otherwise stated, these types are not populated by the middlewares, they are expected to
+//! be inserted in the request extensions by the server (or by another middleware) before the
+//! request is handled.
+
+use std::{
+    fmt::Display,
+    sync::{atomic::AtomicU32, Arc},
+    time::{Duration, Instant},
+};
+
+/// Parameters negotiated by the TLS connection.
+///
+/// When present in the request extensions, the server [`Span`]s record the `tls.protocol.version`
+/// and `tls.cipher` attributes. The acceptor (e.g. one based on `rustls` or `openssl`) should
+/// insert it in the extensions of each request received on the connection.
+///
+/// The server name sent by the client (SNI) is the authoritative host of a multi-domain server,
+/// when present it is recorded as `server.address`, taking the precedence over the `Host`
+/// header. With `rustls`, the parameters are available from the `ServerConnection` once the
+/// handshake is completed: `server_name()`, `protocol_version()` and
+/// `negotiated_cipher_suite()`.
+///
+/// [`Span`]: tracing::Span
+#[derive(Clone, Debug, Default)]
+pub struct TlsInfo {
+    /// Version of the protocol, e.g. `1.3`.
+    pub protocol_version: Option<String>,
+    /// Cipher suite, e.g. `TLS_AES_128_GCM_SHA256`.
+    pub cipher: Option<String>,
+    /// Server name indication sent by the client, e.g. `example.com`.
+    pub server_name: Option<String>,
+}
+
+/// Transport protocol of the connection, recorded as `network.transport`.
+///
+/// When present in the request extensions, it overrides the transport configured in the layer.
+/// The server should insert it in the extensions of each request received on a connection that
+/// does not use TCP, e.g. an Unix domain socket.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetworkTransport {
+    /// Transmission Control Protocol.
+    #[default]
+    Tcp,
+    /// User Datagram Protocol (e.g. QUIC).
+    Udp,
+    /// Unix domain socket.
+    Unix,
+    /// Named or anonymous pipe.
+    Pipe,
+}
+
+impl NetworkTransport {
+    /// String representation of the transport, as defined by OpenTelemetry.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+            Self::Unix => "unix",
+            Self::Pipe => "pipe",
+        }
+    }
+}
+
+/// Time elapsed between the call of the service and the reception of the response head.
+///
+/// It is inserted by the middlewares in the response extensions, when enabled, so that the
+/// downstream middlewares can read it (e.g. to set a `X-Response-Time` header).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ElapsedTime(pub Duration);
+
+/// [`Span`] of the connection on which the request has been received.
+///
+/// It is inserted in the request extensions by the [`ConnectionLayer`], the request [`Span`]s
+/// are linked to it.
+///
+/// [`ConnectionLayer`]: super::ConnectionLayer
+/// [`Span`]: tracing::Span
+#[derive(Clone, Debug)]
+pub struct ConnectionSpan(pub tracing::Span);
+
+/// [`Span`] of the logical operation, shared by all the attempts of a retried request.
+///
+/// It is inserted in the request extensions by an [`HttpLayer`] configured with
+/// [`HttpLayerBuilder::operation_span`], the client [`Span`]s of the attempts are children of it.
+///
+/// [`HttpLayer`]: super::HttpLayer
+/// [`HttpLayerBuilder::operation_span`]: super::http::HttpLayerBuilder::operation_span
+/// [`Span`]: tracing::Span
+#[derive(Clone, Debug)]
+pub struct OperationSpan {
+    pub(crate) span: tracing::Span,
+    pub(crate) attempts: Arc<AtomicU32>,
+}
+
+/// Handle used by a long-poll handler to signal that the waiting phase is over.
+///
+/// It is inserted in the request extensions by an [`HttpLayer`] configured with
+/// [`HttpLayerBuilder::wait_signal`], the time elapsed between the reception of the request and the
+/// call of [`WaitSignal::ready`] is recorded as `http.server.wait.duration` (in seconds), so that
+/// the waiting is not attributed to the processing of the request.
+///
+/// [`HttpLayer`]: super::HttpLayer
+/// [`HttpLayerBuilder::wait_signal`]: super::http::HttpLayerBuilder::wait_signal
+#[derive(Clone, Debug)]
+pub struct WaitSignal {
+    pub(crate) span: tracing::Span,
+    pub(crate) start: Instant,
+}
+
+impl WaitSignal {
+    /// Signals that the handler has finished waiting and it is starting to process the request.
+    ///
+    /// Only the first call is meaningful, the following ones overwrite the recorded duration.
+    pub fn ready(&self) {
+        let duration = self.start.elapsed().as_secs_f64();
+        self.span.record("http.server.wait.duration", duration);
+    }
+}
+
+/// Error mapped to the response by the handler.
+///
+/// Frameworks like `axum` convert the errors of the handlers into responses, so the inner service
+/// does not fail and the error is not recorded. When present in the response extensions (e.g.
+/// inserted by the `IntoResponse` implementation of the error), the [`HttpLayer`] records it as
+/// `error.message` and `error.type`.
+///
+/// [`HttpLayer`]: super::HttpLayer
+#[derive(Clone, Debug)]
+pub struct ResponseError {
+    /// Message of the error.
+    pub message: String,
+    /// Type of the error, e.g. the name of the Rust type.
+    pub error_type: String,
+}
+
+impl ResponseError {
+    /// Creates the extension from the given error, the type is the name of the Rust type.
+    pub fn new<E: Display>(err: &E) -> Self {
+        Self {
+            message: err.to_string(),
+            error_type: std::any::type_name::<E>().to_owned(),
+        }
+    }
+}
+
+/// Function that handled the request.
+///
+/// The routers do not expose the handler matched for the request, so it is inserted in the
+/// response extensions by the handler itself, using the [`code_function!`] macro. When present,
+/// the [`HttpLayer`] records the fully qualified name as `code.function.name`: the deprecated
+/// `code.namespace` attribute is not used, since it is already recorded by
+/// `tracing-opentelemetry` as the module of the middleware. With `axum`, the handler can return
+/// it as part of the response, e.g. `(Extension(tower_otel::code_function!()), body)`.
+///
+/// [`code_function!`]: crate::code_function
+/// [`HttpLayer`]: super::HttpLayer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodeFunction {
+    /// Name of the function, e.g. `get_user`.
+    pub function: &'static str,
+    /// Path of the module of the function, e.g. `my_app::handlers`.
+    pub namespace: &'static str,
+}
+
+impl CodeFunction {
+    /// Fully qualified name of the function, e.g. `my_app::handlers::get_user`.
+    pub fn qualified_name(&self) -> String {
+        if self.namespace.is_empty() {
+            self.function.to_owned()
+        } else {
+            format!("{}::{}", self.namespace, self.function)
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn from_type_name(name: &'static str) -> Self {
+        let mut name = name.strip_suffix("::f").unwrap_or(name);
+        while let Some(stripped) = name.strip_suffix("::{{closure}}") {
+            name = stripped;
+        }
+        let (namespace, function) = name.rsplit_once("::").unwrap_or(("", name));
+        Self {
+            function,
+            namespace,
+        }
+    }
+}
+
+/// Creates the [`CodeFunction`] of the enclosing function.
+///
+/// [`CodeFunction`]: crate::trace::extensions::CodeFunction
+#[macro_export]
+macro_rules! code_function {
+    () => {{
+        fn f() {}
+        fn type_name_of<T>(_: T) -> &'static str {
+            ::std::any::type_name::<T>()
+        }
+        $crate::trace::extensions::CodeFunction::from_type_name(type_name_of(f))
+    }};
+}
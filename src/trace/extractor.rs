@@ -1,6 +1,7 @@
 //! Implementation of fields injector.
 
 use http::HeaderMap;
+use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
 
 pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
 
@@ -18,3 +19,39 @@ impl<'a> opentelemetry::propagation::Extractor for HeaderExtractor<'a> {
             .collect()
     }
 }
+
+/// Parses the value of a W3C `traceparent` header, e.g.
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+///
+/// The fields appended by the future versions are ignored, the invalid values are rejected.
+pub fn parse_traceparent(traceparent: &str) -> Option<SpanContext> {
+    fn is_hex(value: &str, len: usize) -> bool {
+        value.len() == len
+            && value
+                .bytes()
+                .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    let mut parts = traceparent.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let trace_flags = parts.next()?;
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || parts.next().is_none())
+        && is_hex(trace_id, 32)
+        && is_hex(span_id, 16)
+        && is_hex(trace_flags, 2);
+    if !valid {
+        return None;
+    }
+
+    let trace_id = TraceId::from_hex(trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    let sampled = u8::from_str_radix(trace_flags, 16).ok()? & 1 == 1;
+    let trace_flags = TraceFlags::default().with_sampled(sampled);
+    let span_context =
+        SpanContext::new(trace_id, span_id, trace_flags, true, TraceState::default());
+    span_context.is_valid().then_some(span_context)
+}
@@ -1,51 +1,391 @@
 //! Middleware that adds tracing to a [`Service`] that handles gRPC requests.
 
 use std::{
-    fmt::Display,
+    fmt::{self, Display},
     future::Future,
     pin::Pin,
+    sync::Arc,
     task::{ready, Context, Poll},
 };
 
-use http::{Request, Response};
-use pin_project::pin_project;
+use http::{HeaderMap, Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::{propagation::TextMapPropagator, trace::TraceContextExt, KeyValue};
+use pin_project::{pin_project, pinned_drop};
 use tower_layer::Layer;
 use tower_service::Service;
 use tracing::{Level, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use super::{extractor::HeaderExtractor, injector::HeaderInjector};
+use crate::util::{
+    authority_host, authority_port, client_address, http_version, MessageSize, WebTrailers,
+};
+
+use super::{
+    extensions::{ConnectionSpan, TlsInfo},
+    extractor::HeaderExtractor,
+    headers::{record_headers, HeaderPolicy, NonUtf8HeaderValue},
+    injector::{inject_context, with_propagator, InjectMode, Propagator},
+    record::{record_error, record_tls_info, request_authority},
+    SpanKind,
+};
+
+/// Predicate used to select the RPCs that are traced.
+type FilterFn = dyn Fn(&str, &str) -> bool + Send + Sync;
 
-/// Describes the relationship between the [`Span`] and the service producing the span.
-#[derive(Clone, Copy, Debug)]
-enum SpanKind {
-    /// The span describes a request sent to some remote service.
-    Client,
-    /// The span describes the server-side handling of a request.
-    Server,
+/// Wrapper of [`FilterFn`] implementing [`Debug`](fmt::Debug).
+#[derive(Clone)]
+struct Filter(Arc<FilterFn>);
+
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Filter")
+    }
 }
 
-/// [`Layer`] that adds tracing to a [`Service`] that handles gRRC requests.
+/// Predicate used to classify the status codes that are errors.
+type StatusErrorFn = dyn Fn(i32) -> bool + Send + Sync;
+
+/// Wrapper of [`StatusErrorFn`] implementing [`Debug`](fmt::Debug).
+#[derive(Clone)]
+struct StatusError(Arc<StatusErrorFn>);
+
+impl fmt::Debug for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StatusError")
+    }
+}
+
+/// Configuration shared by [`GrpcLayer`] and [`Grpc`].
 #[derive(Clone, Debug)]
-pub struct GrpcLayer {
+struct Config {
     level: Level,
     kind: SpanKind,
+    filter: Option<Filter>,
+    header_policy: HeaderPolicy,
+    non_utf8_header_value: NonUtf8HeaderValue,
+    max_header_value_len: Option<usize>,
+    attributes: Arc<[KeyValue]>,
+    message_size: bool,
+    status_error: Option<StatusError>,
+    propagation: bool,
+    inject_mode: InjectMode,
+    propagator: Option<Propagator>,
+    sampled_attribute: bool,
+    error_events: bool,
+    rpc_system: Option<String>,
+}
+
+impl Config {
+    fn new(level: Level, kind: SpanKind) -> Self {
+        GrpcTraceConfig {
+            level,
+            kind,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    /// Checks if the status code should be considered an error.
+    fn is_error(&self, status_code: i32) -> bool {
+        match (&self.status_error, self.kind) {
+            (Some(StatusError(status_error)), _) => status_error(status_code),
+            (None, SpanKind::Client) => status_code != 0,
+            // UNKNOWN, DEADLINE_EXCEEDED, UNIMPLEMENTED, INTERNAL, UNAVAILABLE, DATA_LOSS
+            (None, SpanKind::Server) => matches!(status_code, 2 | 4 | 12 | 13 | 14 | 15),
+        }
+    }
+}
+
+impl From<GrpcTraceConfig> for Config {
+    fn from(config: GrpcTraceConfig) -> Self {
+        Self {
+            level: config.level,
+            kind: config.kind,
+            filter: None,
+            header_policy: config.header_policy,
+            non_utf8_header_value: config.non_utf8_header_value,
+            max_header_value_len: config.max_header_value_len,
+            attributes: Arc::from(config.attributes),
+            message_size: config.message_size,
+            status_error: None,
+            propagation: config.propagation,
+            inject_mode: config.inject_mode,
+            propagator: None,
+            sampled_attribute: config.sampled_attribute,
+            error_events: config.error_events,
+            rpc_system: config.rpc_system,
+        }
+    }
+}
+
+/// Options of the [`GrpcLayer`], it can be used to configure the layer in a single place (e.g.
+/// from a configuration file) with [`GrpcLayer::from_config`].
+///
+/// The options taking a function (the filter and the classification of the status codes) can be
+/// set only by the [`GrpcLayerBuilder`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct GrpcTraceConfig {
+    /// Level of the [`Span`]s, by default [`Level::INFO`].
+    pub level: Level,
+    /// Kind of the [`Span`]s, by default [`SpanKind::Server`].
+    pub kind: SpanKind,
+    /// See [`GrpcLayerBuilder::header_policy`].
+    pub header_policy: HeaderPolicy,
+    /// See [`GrpcLayerBuilder::non_utf8_header_value`].
+    pub non_utf8_header_value: NonUtf8HeaderValue,
+    /// See [`GrpcLayerBuilder::max_header_value_len`].
+    pub max_header_value_len: Option<usize>,
+    /// See [`GrpcLayerBuilder::attributes`].
+    pub attributes: Vec<KeyValue>,
+    /// See [`GrpcLayerBuilder::message_size`].
+    pub message_size: bool,
+    /// See [`GrpcLayerBuilder::propagation`].
+    pub propagation: bool,
+    /// See [`GrpcLayerBuilder::inject_mode`].
+    pub inject_mode: InjectMode,
+    /// See [`GrpcLayerBuilder::sampled_attribute`].
+    pub sampled_attribute: bool,
+    /// See [`GrpcLayerBuilder::error_events`].
+    pub error_events: bool,
+    /// See [`GrpcLayerBuilder::rpc_system`].
+    pub rpc_system: Option<String>,
+}
+
+impl Default for GrpcTraceConfig {
+    fn default() -> Self {
+        Self {
+            level: Level::INFO,
+            kind: SpanKind::default(),
+            header_policy: HeaderPolicy::default(),
+            non_utf8_header_value: NonUtf8HeaderValue::default(),
+            max_header_value_len: None,
+            attributes: Vec::new(),
+            message_size: false,
+            propagation: true,
+            inject_mode: InjectMode::default(),
+            sampled_attribute: false,
+            error_events: false,
+            rpc_system: None,
+        }
+    }
+}
+
+/// [`Layer`] that adds tracing to a [`Service`] that handles gRRC requests.
+#[derive(Clone, Debug)]
+pub struct GrpcLayer {
+    config: Arc<Config>,
 }
 
 impl GrpcLayer {
     /// [`Span`]s are constructed at the given level from server side.
+    ///
+    /// The parent of the [`Span`] is the remote context extracted from the request headers, if
+    /// the request does not carry a valid remote context, a [`Span`] inserted in the request
+    /// extensions (e.g. a span started in the connection accept loop) is used as parent.
+    ///
+    /// The parent is set before the sampling decision is taken, so the sampled flag of the remote
+    /// context is honored when the tracer is configured with a parent based sampler (the default
+    /// of the OpenTelemetry SDK).
     pub fn server(level: Level) -> Self {
         Self {
-            level,
-            kind: SpanKind::Server,
+            config: Arc::new(Config::new(level, SpanKind::Server)),
         }
     }
 
     /// [`Span`]s are constructed at the given level from client side.
     pub fn client(level: Level) -> Self {
         Self {
-            level,
-            kind: SpanKind::Client,
+            config: Arc::new(Config::new(level, SpanKind::Client)),
+        }
+    }
+
+    /// The layer is configured with the given options.
+    pub fn from_config(config: GrpcTraceConfig) -> Self {
+        Self {
+            config: Arc::new(config.into()),
+        }
+    }
+
+    /// Returns a builder to configure the layer, by default [`Span`]s are constructed at the
+    /// [`Level::INFO`] from server side.
+    pub fn builder() -> GrpcLayerBuilder {
+        GrpcLayerBuilder {
+            config: Config::new(Level::INFO, SpanKind::Server),
+        }
+    }
+}
+
+/// Builder of [`GrpcLayer`].
+#[derive(Clone, Debug)]
+pub struct GrpcLayerBuilder {
+    config: Config,
+}
+
+impl GrpcLayerBuilder {
+    /// [`Span`]s are constructed from server side.
+    pub fn server(mut self) -> Self {
+        self.config.kind = SpanKind::Server;
+        self
+    }
+
+    /// [`Span`]s are constructed from client side.
+    pub fn client(mut self) -> Self {
+        self.config.kind = SpanKind::Client;
+        self
+    }
+
+    /// [`Span`]s are constructed at the given level.
+    pub fn level(mut self, level: Level) -> Self {
+        self.config.level = level;
+        self
+    }
+
+    /// Only the RPCs for which the predicate returns `true` are traced.
+    ///
+    /// The predicate receives the service and the method names parsed from the request path, it
+    /// is evaluated before the [`Span`] is constructed, so skipped RPCs (like health checks or
+    /// reflection) do not pay the cost of tracing. The requests with a malformed path are always
+    /// traced.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.config.filter = Some(Filter(Arc::new(filter)));
+        self
+    }
+
+    /// Selects the metadata recorded as `rpc.grpc.{request,response}.metadata.*`, by default all
+    /// the metadata are recorded.
+    ///
+    /// When the request passes through several tracing layers, the metadata should be recorded
+    /// only by one of them, using [`HeaderPolicy::Off`] on the others.
+    pub fn header_policy(mut self, header_policy: HeaderPolicy) -> Self {
+        self.config.header_policy = header_policy;
+        self
+    }
+
+    /// Selects how the metadata values that are not valid UTF-8 are recorded, by default they are
+    /// skipped.
+    pub fn non_utf8_header_value(mut self, non_utf8_header_value: NonUtf8HeaderValue) -> Self {
+        self.config.non_utf8_header_value = non_utf8_header_value;
+        self
+    }
+
+    /// The recorded metadata values are truncated to the given length in bytes, marking the
+    /// truncation with `...`, by default the values are not truncated.
+    ///
+    /// It bounds the size of the [`Span`] when the metadata carries very long values (e.g. a
+    /// bearer token in `authorization` or a custom metadata holding a serialized payload).
+    pub fn max_header_value_len(mut self, max_header_value_len: usize) -> Self {
+        self.config.max_header_value_len = Some(max_header_value_len);
+        self
+    }
+
+    /// The given attributes are recorded in each [`Span`], e.g. the `service.namespace` of the
+    /// logical service when several services are hosted by the same process.
+    pub fn attributes(mut self, attributes: Vec<KeyValue>) -> Self {
+        self.config.attributes = Arc::from(attributes);
+        self
+    }
+
+    /// Enables or disables the recording of the size of the response messages as
+    /// `rpc.grpc.response.size`, by default the size is not recorded.
+    ///
+    /// The size is the one sent on the wire minus the framing, i.e. the sum of the lengths of the
+    /// messages without their 5-byte prefix, the messages are not decompressed. It matches the
+    /// message size shown by most of the gRPC dashboards. The size is not recorded for the gRPC-Web
+    /// responses, whose body also contains the trailers.
+    ///
+    /// The size of the request messages is recorded by [`RequestSizeLayer`], since it requires to
+    /// wrap the request body passed to the inner service.
+    pub fn message_size(mut self, enabled: bool) -> Self {
+        self.config.message_size = enabled;
+        self
+    }
+
+    /// The [`Span`] is marked as failed when the predicate returns `true` for the status code of
+    /// the response.
+    ///
+    /// By default, from client side every non-zero status code is an error, while from server
+    /// side only the status codes signaling a failure of the server are errors (`UNKNOWN`,
+    /// `DEADLINE_EXCEEDED`, `UNIMPLEMENTED`, `INTERNAL`, `UNAVAILABLE` and `DATA_LOSS`), as
+    /// suggested by the OpenTelemetry semantic conventions.
+    pub fn status_error<F>(mut self, status_error: F) -> Self
+    where
+        F: Fn(i32) -> bool + Send + Sync + 'static,
+    {
+        self.config.status_error = Some(StatusError(Arc::new(status_error)));
+        self
+    }
+
+    /// Enables or disables the injection of the context in the request headers from client side,
+    /// by default the context is injected.
+    ///
+    /// When an [`opentelemetry::Context`] is found in the request extensions, the client [`Span`]
+    /// is its child, so that the requests built outside of `tracing` are propagated in the trace
+    /// of the given context.
+    ///
+    /// It can be disabled to avoid leaking the propagation headers to untrusted services.
+    pub fn propagation(mut self, enabled: bool) -> Self {
+        self.config.propagation = enabled;
+        self
+    }
+
+    /// Selects how the context is injected in the request headers from client side, by default
+    /// the existing propagation headers are overwritten.
+    pub fn inject_mode(mut self, inject_mode: InjectMode) -> Self {
+        self.config.inject_mode = inject_mode;
+        self
+    }
+
+    /// Sets the propagator used to extract and inject the context, in place of the global one.
+    ///
+    /// It avoids the mutation of the global state, e.g. in the tests or when the services of a
+    /// process use different propagation formats.
+    pub fn propagator<P>(mut self, propagator: P) -> Self
+    where
+        P: TextMapPropagator + Send + Sync + 'static,
+    {
+        self.config.propagator = Some(Propagator(Arc::new(propagator)));
+        self
+    }
+
+    /// Enables or disables the recording of the sampling decision as `otel.sampled`, by default
+    /// it is not recorded.
+    ///
+    /// The sampled flag is read after the parent is set, so it reflects the decision taken by the
+    /// local sampler, it helps diagnosing the traces missing from the backend (e.g. with a tail
+    /// sampling collector receiving also the unsampled spans).
+    pub fn sampled_attribute(mut self, enabled: bool) -> Self {
+        self.config.sampled_attribute = enabled;
+        self
+    }
+
+    /// Enables or disables the emission of an event at the [`Level::ERROR`] when the inner service
+    /// fails, by default the event is not emitted.
+    ///
+    /// The event is emitted inside the [`Span`] and it carries the `error.message` and the
+    /// `error.type`, so that the failure is visible also in the logs.
+    pub fn error_events(mut self, enabled: bool) -> Self {
+        self.config.error_events = enabled;
+        self
+    }
+
+    /// The given value is recorded as `rpc.system`.
+    ///
+    /// By default it is detected from the request headers: `connect_rpc` for the requests using
+    /// the Connect protocol, `grpc` otherwise (gRPC-Web requests are marked by `rpc.grpc.web`).
+    pub fn rpc_system(mut self, rpc_system: impl Into<String>) -> Self {
+        self.config.rpc_system = Some(rpc_system.into());
+        self
+    }
+
+    /// Returns the configured [`GrpcLayer`].
+    pub fn build(self) -> GrpcLayer {
+        GrpcLayer {
+            config: Arc::new(self.config),
         }
     }
 }
@@ -56,26 +396,39 @@ impl<S> Layer<S> for GrpcLayer {
     fn layer(&self, inner: S) -> Self::Service {
         Grpc {
             inner,
-            level: self.level,
-            kind: self.kind,
+            config: self.config.clone(),
         }
     }
 }
 
 /// Middleware that adds tracing to a [`Service`] that handles gRPC requests.
+///
+/// The inner service is not required to be [`Clone`], the middleware is cloneable only when the
+/// inner service is.
 #[derive(Clone, Debug)]
 pub struct Grpc<S> {
     inner: S,
-    level: Level,
-    kind: SpanKind,
+    config: Arc<Config>,
+}
+
+impl<S> Grpc<S> {
+    /// Wraps the given service, it is equivalent to [`GrpcLayer::server`].
+    pub fn new_server(inner: S, level: Level) -> Self {
+        GrpcLayer::server(level).layer(inner)
+    }
+
+    /// Wraps the given service, it is equivalent to [`GrpcLayer::client`].
+    pub fn new_client(inner: S, level: Level) -> Self {
+        GrpcLayer::client(level).layer(inner)
+    }
 }
 
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Grpc<S>
 where
     S: Service<Request<ReqBody>, Response = Response<ResBody>>,
-    S::Error: Display,
+    S::Error: Display + 'static,
 {
-    type Response = S::Response;
+    type Response = Response<ResponseBody<ResBody>>;
     type Error = S::Error;
     type Future = ResponseFuture<S::Future>;
 
@@ -84,48 +437,384 @@ where
     }
 
     fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
-        let span = make_request_span(self.level, self.kind, &mut req);
+        if let Some(Filter(filter)) = &self.config.filter {
+            if let Some((service, method)) = rpc_name(req.uri().path()) {
+                if !filter(service, method) {
+                    let inner = self.inner.call(req);
+                    let span = Span::none();
+                    return ResponseFuture {
+                        inner,
+                        span,
+                        config: self.config.clone(),
+                        web: false,
+                        completed: false,
+                    };
+                }
+            }
+        }
+
+        let web = is_grpc_web(&req);
+        let span = make_request_span(&self.config, &mut req);
+        if !span.is_disabled() {
+            req.extensions_mut().insert(span.context());
+        }
         let inner = {
             let _enter = span.enter();
             self.inner.call(req)
         };
 
-        ResponseFuture { inner, span }
+        ResponseFuture {
+            inner,
+            span,
+            config: self.config.clone(),
+            web,
+            completed: false,
+        }
+    }
+}
+
+/// [`Layer`] that records the size of the request messages as `rpc.grpc.request.size`.
+///
+/// It must be placed just inside a [`GrpcLayer`]: the size is recorded in the current [`Span`]
+/// when the inner service is called, i.e. the one constructed by [`Grpc`]. The size is measured
+/// as described in [`GrpcLayerBuilder::message_size`], it is not recorded for the gRPC-Web
+/// requests.
+///
+/// The request body is passed to the inner service wrapped in a [`RequestBody`], so the inner
+/// service must accept it, that is why it is not part of [`GrpcLayer`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestSizeLayer {
+    _priv: (),
+}
+
+impl RequestSizeLayer {
+    /// Creates a new [`RequestSizeLayer`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> Layer<S> for RequestSizeLayer {
+    type Service = RequestSize<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestSize { inner }
+    }
+}
+
+/// Middleware that records the size of the request messages, see [`RequestSizeLayer`].
+#[derive(Clone, Debug)]
+pub struct RequestSize<S> {
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RequestSize<S>
+where
+    S: Service<Request<RequestBody<ReqBody>>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let span = Span::current();
+        let message_size = (!span.is_disabled() && !is_grpc_web(&req)).then(MessageSize::new);
+        let req = req.map(|inner| RequestBody {
+            inner,
+            span: message_size.is_some().then_some(span),
+            message_size,
+        });
+        self.inner.call(req)
+    }
+}
+
+/// Request body for [`RequestSize`].
+///
+/// The body keeps the [`Span`] alive until the end of the stream to record the size of the
+/// messages.
+#[pin_project(PinnedDrop)]
+pub struct RequestBody<B> {
+    #[pin]
+    inner: B,
+    span: Option<Span>,
+    message_size: Option<MessageSize>,
+}
+
+impl<B: Body> Body for RequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+
+        let Some(span) = this.span else {
+            return Poll::Ready(frame);
+        };
+        match &frame {
+            Some(Ok(frame)) => {
+                if let (Some(message_size), Some(data)) =
+                    (this.message_size.as_mut(), frame.data_ref())
+                {
+                    message_size.update(data);
+                }
+                if frame.is_trailers() {
+                    record_message_size(span, REQUEST_SIZE, this.message_size.take());
+                }
+            }
+            Some(Err(_)) => {}
+            None => record_message_size(span, REQUEST_SIZE, this.message_size.take()),
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[pinned_drop]
+impl<B> PinnedDrop for RequestBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        // the inner service may stop reading once the expected messages are received
+        let this = self.project();
+        if let Some(span) = this.span {
+            record_message_size(span, REQUEST_SIZE, this.message_size.take());
+        }
     }
 }
 
 /// Response future for [`Grpc`].
-#[pin_project]
+///
+/// When the future is dropped before the response is received (e.g. the request is cancelled by
+/// the client), the status code is recorded as `CANCELLED` (1) and the [`Span`] is always marked
+/// as failed, also from server side where `CANCELLED` is not an error when it is received from
+/// the inner service.
+#[pin_project(PinnedDrop)]
 pub struct ResponseFuture<F> {
     #[pin]
     inner: F,
     span: Span,
+    config: Arc<Config>,
+    web: bool,
+    completed: bool,
 }
 
 impl<F, ResBody, E> Future for ResponseFuture<F>
 where
     F: Future<Output = Result<Response<ResBody>, E>>,
-    E: Display,
+    E: Display + 'static,
 {
-    type Output = Result<Response<ResBody>, E>;
+    type Output = Result<Response<ResponseBody<ResBody>>, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
         let _enter = this.span.enter();
 
-        match ready!(this.inner.poll(cx)) {
+        let result = ready!(this.inner.poll(cx));
+        *this.completed = true;
+
+        // The RPC is skipped by the filter or the span is disabled, there is nothing to record.
+        if this.span.is_none() {
+            let config = this.config.clone();
+            return Poll::Ready(result.map(|response| {
+                response.map(|inner| ResponseBody {
+                    inner,
+                    span: None,
+                    config,
+                    message_size: None,
+                    web_trailers: None,
+                })
+            }));
+        }
+
+        match result {
             Ok(response) => {
-                record_response(this.span, &response);
+                let status_found = record_response(this.span, this.config, &response);
+                let web_trailers = (*this.web && !status_found && !is_grpc_web_text(&response))
+                    .then(WebTrailers::new);
+                let message_size = (this.config.message_size && !*this.web).then(MessageSize::new);
+                let span = ((!status_found && !*this.web)
+                    || web_trailers.is_some()
+                    || message_size.is_some())
+                .then(|| this.span.clone());
+                let config = this.config.clone();
+                let response = response.map(|inner| ResponseBody {
+                    inner,
+                    span,
+                    config,
+                    message_size,
+                    web_trailers,
+                });
                 Poll::Ready(Ok(response))
             }
             Err(err) => {
-                record_error(this.span, &err);
+                let event_target = this
+                    .config
+                    .error_events
+                    .then(|| span_target(this.config.kind));
+                record_error(this.span, &err, event_target);
                 Poll::Ready(Err(err))
             }
         }
     }
 }
 
+#[pinned_drop]
+impl<F> PinnedDrop for ResponseFuture<F> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if !*this.completed {
+            this.span.record("rpc.grpc.status_code", 1);
+            this.span.record("otel.status_code", "ERROR");
+        }
+    }
+}
+
+/// Response body for [`Grpc`].
+///
+/// When the status code is not sent in the response headers, the body keeps the [`Span`] alive
+/// until the trailers are received, if the stream ends without a status code it is not recorded.
+/// The trailers of gRPC-Web responses are parsed from the last frame of the body, except for the
+/// text format (`application/grpc-web-text`) whose status code is not recorded.
+///
+/// When enabled by [`GrpcLayerBuilder::message_size`], the body keeps the [`Span`] alive until the
+/// end of the stream to record the size of the messages.
+#[pin_project]
+pub struct ResponseBody<B> {
+    #[pin]
+    inner: B,
+    span: Option<Span>,
+    config: Arc<Config>,
+    message_size: Option<MessageSize>,
+    web_trailers: Option<WebTrailers>,
+}
+
+impl<B: Body> Body for ResponseBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+
+        let Some(span) = this.span else {
+            return Poll::Ready(frame);
+        };
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    if let Some(message_size) = this.message_size.as_mut() {
+                        message_size.update(data);
+                    }
+                    let web_trailers = this.web_trailers.as_mut();
+                    if let Some(trailers) = web_trailers.and_then(|parser| parser.update(data)) {
+                        record_metadata(span, this.config, &trailers);
+                        *this.web_trailers = None;
+                    }
+                }
+                if let Some(trailers) = frame.trailers_ref() {
+                    record_metadata(span, this.config, trailers);
+                    record_message_size(span, RESPONSE_SIZE, this.message_size.take());
+                }
+            }
+            Some(Err(_)) => {}
+            None => record_message_size(span, RESPONSE_SIZE, this.message_size.take()),
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Service and method names parsed from the request path
+///
+/// The path of a gRPC request has the form `/package.Service/Method`, the other paths are
+/// malformed and the names are not returned.
+fn rpc_name(path: &str) -> Option<(&str, &str)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    let valid = !service.is_empty() && !method.is_empty() && !method.contains('/');
+    valid.then_some((service, method))
+}
+
+/// Checks if the request is a gRPC-Web request
+fn is_grpc_web<B>(request: &Request<B>) -> bool {
+    request
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|header_value| header_value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/grpc-web"))
+}
+
+/// Checks if the response uses the text format of gRPC-Web, i.e. the body is base64 encoded
+fn is_grpc_web_text<B>(response: &Response<B>) -> bool {
+    response
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|header_value| header_value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/grpc-web-text"))
+}
+
+/// Encoding of the messages, derived from the suffix of the gRPC content type
+fn message_encoding<B>(request: &Request<B>) -> Option<&str> {
+    let content_type = request
+        .headers()
+        .get(http::header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?;
+    let content_type = content_type.split(';').next()?.trim();
+    let suffix = [
+        "application/grpc-web-text",
+        "application/grpc-web",
+        "application/grpc",
+    ]
+    .into_iter()
+    .find_map(|prefix| content_type.strip_prefix(prefix))?;
+    match suffix {
+        "" => Some("proto"),
+        suffix => suffix
+            .strip_prefix('+')
+            .filter(|encoding| !encoding.is_empty()),
+    }
+}
+
+/// Detects the RPC system from the request headers
+fn rpc_system<B>(request: &Request<B>) -> &'static str {
+    let headers = request.headers();
+    let connect = headers.contains_key("connect-protocol-version")
+        || headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|header_value| header_value.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("application/connect"));
+    if connect {
+        "connect_rpc"
+    } else {
+        "grpc"
+    }
+}
+
 /// String representation of span kind
 fn span_kind(kind: SpanKind) -> &'static str {
     match kind {
@@ -134,90 +823,302 @@ fn span_kind(kind: SpanKind) -> &'static str {
     }
 }
 
+/// Target of the [`Span`]s of the given kind.
+fn span_target(kind: SpanKind) -> &'static str {
+    match kind {
+        SpanKind::Client => CLIENT_TARGET,
+        SpanKind::Server => SERVER_TARGET,
+    }
+}
+
+/// Target of the client [`Span`]s.
+pub(crate) const CLIENT_TARGET: &str = "tower_otel::trace::grpc::client";
+
+/// Target of the server [`Span`]s.
+pub(crate) const SERVER_TARGET: &str = "tower_otel::trace::grpc::server";
+
+/// Attribute recording the size of the request messages.
+const REQUEST_SIZE: &str = "rpc.grpc.request.size";
+
+/// Attribute recording the size of the response messages.
+const RESPONSE_SIZE: &str = "rpc.grpc.response.size";
+
 /// Creates a new [`Span`] for the given request.
-fn make_request_span<B>(level: Level, kind: SpanKind, request: &mut Request<B>) -> Span {
+fn make_request_span<B>(config: &Config, request: &mut Request<B>) -> Span {
+    let kind = config.kind;
+    let rpc_system = config
+        .rpc_system
+        .as_deref()
+        .unwrap_or_else(|| rpc_system(request));
     macro_rules! make_span {
-        ($level:expr) => {{
+        ($level:expr, $target:expr) => {{
             use tracing::field::Empty;
 
             tracing::span!(
+                target: $target,
                 $level,
                 "GRPC",
+                "client.address" = Empty,
+                "client.port" = Empty,
                 "error.message" = Empty,
+                "error.type" = Empty,
+                "network.protocol.name" = "http",
+                "network.protocol.version" = http_version(request.version()),
                 "otel.kind" = span_kind(kind),
                 "otel.name" = Empty,
                 "otel.status_code" = Empty,
+                "otel.status_message" = Empty,
+                "rpc.grpc.request.size" = Empty,
+                "rpc.grpc.response.size" = Empty,
                 "rpc.grpc.status_code" = Empty,
+                "rpc.grpc.web" = Empty,
+                "rpc.message.encoding" = Empty,
                 "rpc.method" = Empty,
                 "rpc.service" = Empty,
-                "rpc.system" = "grpc",
+                "rpc.system" = rpc_system,
+                "server.address" = Empty,
+                "server.port" = Empty,
+                "tls.cipher" = Empty,
+                "tls.protocol.version" = Empty,
             )
         }};
     }
 
-    let span = match level {
-        Level::ERROR => make_span!(Level::ERROR),
-        Level::WARN => make_span!(Level::WARN),
-        Level::INFO => make_span!(Level::INFO),
-        Level::DEBUG => make_span!(Level::DEBUG),
-        Level::TRACE => make_span!(Level::TRACE),
+    let span = match (config.level, kind) {
+        (Level::ERROR, SpanKind::Client) => make_span!(Level::ERROR, CLIENT_TARGET),
+        (Level::WARN, SpanKind::Client) => make_span!(Level::WARN, CLIENT_TARGET),
+        (Level::INFO, SpanKind::Client) => make_span!(Level::INFO, CLIENT_TARGET),
+        (Level::DEBUG, SpanKind::Client) => make_span!(Level::DEBUG, CLIENT_TARGET),
+        (Level::TRACE, SpanKind::Client) => make_span!(Level::TRACE, CLIENT_TARGET),
+        (Level::ERROR, SpanKind::Server) => make_span!(Level::ERROR, SERVER_TARGET),
+        (Level::WARN, SpanKind::Server) => make_span!(Level::WARN, SERVER_TARGET),
+        (Level::INFO, SpanKind::Server) => make_span!(Level::INFO, SERVER_TARGET),
+        (Level::DEBUG, SpanKind::Server) => make_span!(Level::DEBUG, SERVER_TARGET),
+        (Level::TRACE, SpanKind::Server) => make_span!(Level::TRACE, SERVER_TARGET),
     };
 
-    for (header_name, header_value) in request.headers().iter() {
-        if let Ok(attribute_value) = header_value.to_str() {
-            let attribute_name = format!("rpc.grpc.request.metadata.{}", header_name);
-            span.set_attribute(attribute_name, attribute_value.to_owned());
+    // The span is filtered out, neither the attributes nor the context are worth the cost
+    // of recording or propagating them.
+    if span.is_disabled() {
+        return span;
+    }
+
+    record_headers(
+        &span,
+        "rpc.grpc.request.metadata",
+        request.headers(),
+        &config.header_policy,
+        config.non_utf8_header_value,
+        config.max_header_value_len,
+        true,
+    );
+
+    for attribute in config.attributes.iter() {
+        span.set_attribute(attribute.key.clone(), attribute.value.clone());
+    }
+
+    match rpc_name(request.uri().path()) {
+        Some((service, method)) => {
+            span.record("otel.name", format!("{}/{}", service, method));
+            span.record("rpc.service", service);
+            span.record("rpc.method", method);
+        }
+        None => {
+            span.record("otel.name", rpc_system);
         }
     }
+    if is_grpc_web(request) {
+        span.record("rpc.grpc.web", true);
+    }
+    if let Some(message_encoding) = message_encoding(request) {
+        span.record("rpc.message.encoding", message_encoding);
+    }
 
-    let path = request.uri().path();
-    let name = path.trim_start_matches('/');
-    span.record("otel.name", name);
-    if let Some((service, method)) = name.split_once('/') {
-        span.record("rpc.service", service);
-        span.record("rpc.method", method);
+    let authority = match kind {
+        SpanKind::Client => request.uri().authority().cloned(),
+        SpanKind::Server => request_authority(request),
+    };
+    let server_name = match kind {
+        SpanKind::Client => None,
+        SpanKind::Server => request
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(|tls_info| tls_info.server_name.as_deref()),
+    };
+    if let Some(server_address) = server_name.or(authority.as_ref().map(authority_host)) {
+        span.record("server.address", server_address);
+    }
+    let server_port = authority
+        .as_ref()
+        .and_then(|authority| authority_port(authority, request.uri().scheme_str()));
+    if let Some(server_port) = server_port {
+        span.record("server.port", server_port as i64);
     }
 
     match kind {
         SpanKind::Client => {
-            let context = span.context();
-            opentelemetry::global::get_text_map_propagator(|injector| {
-                injector.inject_context(&context, &mut HeaderInjector(request.headers_mut()));
-            });
+            if let Some(context) = request.extensions().get::<opentelemetry::Context>() {
+                if context.span().span_context().is_valid() {
+                    span.set_parent(context.clone());
+                }
+            }
+
+            if config.propagation {
+                let context = span.context();
+                inject_context(
+                    config.propagator.as_ref(),
+                    &context,
+                    request.headers_mut(),
+                    config.inject_mode,
+                );
+            }
         }
         SpanKind::Server => {
-            let context = opentelemetry::global::get_text_map_propagator(|extractor| {
-                extractor.extract(&HeaderExtractor(request.headers_mut()))
+            let context = with_propagator(config.propagator.as_ref(), |propagator| {
+                propagator.extract(&HeaderExtractor(request.headers_mut()))
             });
-            span.set_parent(context);
+            match request.extensions().get::<Span>() {
+                Some(parent) if !context.span().span_context().is_valid() => {
+                    span.set_parent(parent.context())
+                }
+                _ => span.set_parent(context),
+            }
+
+            if let Some((client_address, client_port)) = client_address(request) {
+                span.record("client.address", client_address);
+                if let Some(client_port) = client_port {
+                    span.record("client.port", client_port as i64);
+                }
+            }
+
+            if let Some(tls_info) = request.extensions().get::<TlsInfo>() {
+                record_tls_info(&span, tls_info);
+            }
+
+            if let Some(ConnectionSpan(connection_span)) = request.extensions().get() {
+                let connection_context = connection_span.context();
+                let span_context = connection_context.span().span_context().clone();
+                span.add_link(span_context);
+            }
         }
     }
+    if config.sampled_attribute {
+        let sampled = span.context().span().span_context().is_sampled();
+        span.set_attribute("otel.sampled", sampled);
+    }
 
     span
 }
 
-/// Records fields associated to the response.
-fn record_response<B>(span: &Span, response: &Response<B>) {
-    for (header_name, header_value) in response.headers().iter() {
-        if let Ok(attribute_value) = header_value.to_str() {
-            let attribute_name = format!("rpc.grpc.response.metadata.{}", header_name);
-            span.set_attribute(attribute_name, attribute_value.to_owned());
+/// Records fields associated to the response, returns `true` if the status code is found.
+///
+/// The status code is found in the headers only for trailers-only responses, usually it is sent
+/// in the trailers.
+fn record_response<B>(span: &Span, config: &Config, response: &Response<B>) -> bool {
+    record_metadata(span, config, response.headers())
+}
+
+/// Records the response metadata (headers or trailers), returns `true` if the status code is
+/// found.
+fn record_metadata(span: &Span, config: &Config, metadata: &HeaderMap) -> bool {
+    record_headers(
+        span,
+        "rpc.grpc.response.metadata",
+        metadata,
+        &config.header_policy,
+        config.non_utf8_header_value,
+        config.max_header_value_len,
+        true,
+    );
+
+    let status_code = metadata
+        .get("grpc-status")
+        .and_then(|header_value| header_value.to_str().ok())
+        .and_then(|header_value| header_value.parse::<i32>().ok());
+    if let Some(status_code) = status_code {
+        record_status_code(span, config, status_code);
+        if config.is_error(status_code) {
+            let status_name = status_name(status_code);
+            let status_message = metadata
+                .get("grpc-message")
+                .and_then(|header_value| header_value.to_str().ok())
+                .map(percent_decode)
+                .or_else(|| status_name.map(str::to_owned));
+            if let Some(status_message) = status_message {
+                span.record("otel.status_message", status_message.as_str());
+                span.record("error.message", status_message);
+            }
+            match status_name {
+                Some(status_name) => span.record("error.type", status_name),
+                None => span.record("error.type", status_code.to_string()),
+            };
         }
     }
+    status_code.is_some()
+}
+
+/// Name of the status code, as defined by gRPC
+fn status_name(status_code: i32) -> Option<&'static str> {
+    let status_name = match status_code {
+        0 => "OK",
+        1 => "CANCELLED",
+        2 => "UNKNOWN",
+        3 => "INVALID_ARGUMENT",
+        4 => "DEADLINE_EXCEEDED",
+        5 => "NOT_FOUND",
+        6 => "ALREADY_EXISTS",
+        7 => "PERMISSION_DENIED",
+        8 => "RESOURCE_EXHAUSTED",
+        9 => "FAILED_PRECONDITION",
+        10 => "ABORTED",
+        11 => "OUT_OF_RANGE",
+        12 => "UNIMPLEMENTED",
+        13 => "INTERNAL",
+        14 => "UNAVAILABLE",
+        15 => "DATA_LOSS",
+        16 => "UNAUTHENTICATED",
+        _ => return None,
+    };
+    Some(status_name)
+}
 
-    if let Some(header_value) = response.headers().get("grpc-status") {
-        if let Ok(header_value) = header_value.to_str() {
-            if let Ok(status_code) = header_value.parse::<i32>() {
-                span.record("rpc.grpc.status_code", status_code);
+/// Decodes the percent-encoded `grpc-message`, invalid sequences are left unchanged.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if hex.iter().all(u8::is_ascii_hexdigit) => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
             }
         }
-    } else {
-        span.record("rpc.grpc.status_code", 0);
     }
+    String::from_utf8_lossy(&decoded).into_owned()
 }
 
-/// Records the error message.
-fn record_error<E: Display>(span: &Span, err: &E) {
-    span.record("otel.status_code", "ERROR");
-    span.record("error.message", err.to_string());
+/// Records the size of the messages as the given attribute, if they have been counted
+fn record_message_size(span: &Span, key: &str, message_size: Option<MessageSize>) {
+    if let Some(total) = message_size.as_ref().and_then(MessageSize::total) {
+        span.record(key, total as i64);
+    }
+}
+
+/// Records the status code, marking the span as failed if the status code is an error.
+fn record_status_code(span: &Span, config: &Config, status_code: i32) {
+    span.record("rpc.grpc.status_code", status_code);
+    if config.is_error(status_code) {
+        span.record("otel.status_code", "ERROR");
+    }
 }
@@ -0,0 +1,201 @@
+//! Recording of the headers as span attributes.
+
+use http::{HeaderMap, HeaderName};
+use opentelemetry::{Array, StringValue, Value};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Describes how the headers are recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// Each header is recorded as a separate attribute holding the array of its values, e.g.
+    /// `http.request.header.accept`.
+    #[default]
+    Attributes,
+    /// All the headers are serialized in a single attribute as a JSON object, e.g.
+    /// `http.request.headers`, the repeated headers are serialized as arrays. It keeps the number
+    /// of attributes low, for the backends that limit it.
+    Json,
+}
+
+/// Describes which headers are recorded as [`Span`] attributes.
+#[derive(Clone, Debug, Default)]
+pub enum HeaderPolicy {
+    /// All the headers are recorded.
+    #[default]
+    All,
+    /// Only the listed headers are recorded.
+    Allow(Vec<HeaderName>),
+    /// All the headers are recorded, except the listed ones.
+    Deny(Vec<HeaderName>),
+    /// No header is recorded.
+    Off,
+}
+
+impl HeaderPolicy {
+    /// Checks if the header should be recorded.
+    pub(crate) fn is_recorded(&self, header_name: &HeaderName) -> bool {
+        match self {
+            Self::All => true,
+            Self::Allow(header_names) => header_names.contains(header_name),
+            Self::Deny(header_names) => !header_names.contains(header_name),
+            Self::Off => false,
+        }
+    }
+}
+
+/// Describes how the header values that are not valid UTF-8 are recorded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonUtf8HeaderValue {
+    /// The value is not recorded.
+    #[default]
+    Skip,
+    /// The value is replaced by the placeholder `<binary len=N>`, where `N` is its length in
+    /// bytes, so that the presence of the header is visible.
+    Placeholder,
+}
+
+/// Records the headers allowed by the policy as attributes named `{prefix}.{header_name}`.
+///
+/// The values are always recorded as arrays of strings, even for the headers sent once, as
+/// required by the semantic conventions. If `skip_binary` is set the headers whose name ends
+/// with `-bin` (binary gRPC metadata) are not recorded. The values longer than `max_value_len`
+/// bytes are truncated.
+pub(crate) fn record_headers(
+    span: &Span,
+    prefix: &str,
+    headers: &HeaderMap,
+    header_policy: &HeaderPolicy,
+    non_utf8_header_value: NonUtf8HeaderValue,
+    max_value_len: Option<usize>,
+    skip_binary: bool,
+) {
+    if span.is_disabled() || matches!(header_policy, HeaderPolicy::Off) {
+        return;
+    }
+
+    for header_name in headers.keys() {
+        if !header_policy.is_recorded(header_name) {
+            continue;
+        }
+        if skip_binary && header_name.as_str().ends_with("-bin") {
+            continue;
+        }
+
+        let attribute_values =
+            header_values(headers, header_name, non_utf8_header_value, max_value_len)
+                .map(StringValue::from)
+                .collect::<Vec<_>>();
+        if attribute_values.is_empty() {
+            continue;
+        }
+        let attribute_value = Value::Array(Array::String(attribute_values));
+
+        let attribute_name = format!("{}.{}", prefix, header_name);
+        span.set_attribute(attribute_name, attribute_value);
+    }
+}
+
+/// Records the headers allowed by the policy as a single JSON object attribute.
+///
+/// The values are filtered and truncated as in [`record_headers`].
+pub(crate) fn record_headers_json(
+    span: &Span,
+    attribute_name: &'static str,
+    headers: &HeaderMap,
+    header_policy: &HeaderPolicy,
+    non_utf8_header_value: NonUtf8HeaderValue,
+    max_value_len: Option<usize>,
+) {
+    if span.is_disabled() || matches!(header_policy, HeaderPolicy::Off) {
+        return;
+    }
+
+    let mut json = String::from("{");
+    for header_name in headers.keys() {
+        if !header_policy.is_recorded(header_name) {
+            continue;
+        }
+
+        let mut values =
+            header_values(headers, header_name, non_utf8_header_value, max_value_len).peekable();
+        let Some(value) = values.next() else {
+            continue;
+        };
+
+        if json.len() > 1 {
+            json.push(',');
+        }
+        push_json_string(&mut json, header_name.as_str());
+        json.push(':');
+        if values.peek().is_none() {
+            push_json_string(&mut json, &value);
+        } else {
+            json.push('[');
+            push_json_string(&mut json, &value);
+            for value in values {
+                json.push(',');
+                push_json_string(&mut json, &value);
+            }
+            json.push(']');
+        }
+    }
+    json.push('}');
+
+    if json.len() > 2 {
+        span.set_attribute(attribute_name, json);
+    }
+}
+
+/// Values of the header, converted as described by the options.
+fn header_values<'a>(
+    headers: &'a HeaderMap,
+    header_name: &HeaderName,
+    non_utf8_header_value: NonUtf8HeaderValue,
+    max_value_len: Option<usize>,
+) -> impl Iterator<Item = String> + 'a {
+    headers
+        .get_all(header_name)
+        .iter()
+        .filter_map(move |header_value| match header_value.to_str() {
+            Ok(value) => Some(value.to_owned()),
+            Err(_) => match non_utf8_header_value {
+                NonUtf8HeaderValue::Skip => None,
+                NonUtf8HeaderValue::Placeholder => {
+                    Some(format!("<binary len={}>", header_value.len()))
+                }
+            },
+        })
+        .map(move |value| match max_value_len {
+            Some(max_value_len) => truncate(value, max_value_len),
+            None => value,
+        })
+}
+
+/// Appends the value to the JSON document as a string literal.
+fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Truncates the value to `max_len` bytes (at a character boundary), appending `...`.
+fn truncate(mut value: String, max_len: usize) -> String {
+    if value.len() <= max_len {
+        return value;
+    }
+    let len = (0..=max_len)
+        .rev()
+        .find(|&len| value.is_char_boundary(len))
+        .unwrap_or(0);
+    value.truncate(len);
+    value.push_str("...");
+    value
+}
@@ -1,51 +1,676 @@
 //! Middleware that adds tracing to a [`Service`] that handles HTTP requests.
 
 use std::{
-    fmt::Display,
+    any::Any,
+    borrow::Cow,
+    fmt::{self, Display},
     future::Future,
+    panic::{self, AssertUnwindSafe},
     pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
     task::{ready, Context, Poll},
+    time::Instant,
 };
 
-use http::{Method, Request, Response, Version};
+use bytes::Buf;
+use http::{uri::Scheme, Extensions, HeaderMap, HeaderName, Method, Request, Response, Version};
+use http_body::{Body, Frame, SizeHint};
+use opentelemetry::{
+    propagation::TextMapPropagator, trace::TraceContextExt, Array, KeyValue, Value,
+};
 use pin_project::pin_project;
 use tower_layer::Layer;
 use tower_service::Service;
 use tracing::{Level, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use super::{extractor::HeaderExtractor, injector::HeaderInjector};
+use crate::util::{
+    authority_host, authority_port, client_address, http_method, http_request_size,
+    http_response_size, http_version,
+};
+
+use super::{
+    extensions::{
+        CodeFunction, ConnectionSpan, ElapsedTime, NetworkTransport, OperationSpan, ResponseError,
+        TlsInfo, WaitSignal,
+    },
+    extractor::{parse_traceparent, HeaderExtractor},
+    headers::{
+        record_headers, record_headers_json, HeaderFormat, HeaderPolicy, NonUtf8HeaderValue,
+    },
+    injector::{inject_context, with_propagator, InjectMode, Propagator},
+    record::{record_error, record_tls_info, request_authority},
+    SpanKind,
+};
 
-/// Describes the relationship between the [`Span`] and the service producing the span.
-#[derive(Clone, Copy, Debug)]
-enum SpanKind {
-    /// The span describes a request sent to some remote service.
-    Client,
-    /// The span describes the server-side handling of a request.
-    Server,
+/// Attributes used to record the matched route of the request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RouteAttributes {
+    /// The route is recorded as `http.route`.
+    HttpRoute,
+    /// The route is recorded as `url.template`.
+    UrlTemplate,
+    /// The route is recorded as both `http.route` and `url.template`.
+    #[default]
+    Both,
 }
 
-/// [`Layer`] that adds tracing to a [`Service`] that handles HTTP requests.
+/// Function returning the attributes read from the request extensions.
+type ExtensionAttributesFn = dyn Fn(&Extensions) -> Vec<KeyValue> + Send + Sync;
+
+/// Wrapper of [`ExtensionAttributesFn`] implementing [`Debug`](fmt::Debug).
+#[derive(Clone)]
+struct ExtensionAttributes(Arc<ExtensionAttributesFn>);
+
+impl fmt::Debug for ExtensionAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ExtensionAttributes")
+    }
+}
+
+/// Function synthesizing the route from the path of the request.
+type RouteNormalizerFn = dyn Fn(&str) -> String + Send + Sync;
+
+/// Wrapper of [`RouteNormalizerFn`] implementing [`Debug`](fmt::Debug).
+#[derive(Clone)]
+struct RouteNormalizer(Arc<RouteNormalizerFn>);
+
+impl fmt::Debug for RouteNormalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RouteNormalizer")
+    }
+}
+
+/// Describes how the query of the request URL is recorded.
+#[derive(Clone, Default)]
+enum UrlQuery {
+    /// The query is recorded verbatim.
+    #[default]
+    Record,
+    /// The query is not recorded.
+    Skip,
+    /// The query is recorded after being redacted.
+    Redact(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+impl fmt::Debug for UrlQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Record => f.write_str("Record"),
+            Self::Skip => f.write_str("Skip"),
+            Self::Redact(_) => f.write_str("Redact"),
+        }
+    }
+}
+
+/// Configuration shared by [`HttpLayer`] and [`Http`].
 #[derive(Clone, Debug)]
-pub struct HttpLayer {
+struct Config {
     level: Level,
     kind: SpanKind,
+    route_attributes: RouteAttributes,
+    route_normalizer: Option<RouteNormalizer>,
+    url_query: UrlQuery,
+    header_policy: HeaderPolicy,
+    non_utf8_header_value: NonUtf8HeaderValue,
+    header_format: HeaderFormat,
+    max_header_value_len: Option<usize>,
+    attributes: Arc<[KeyValue]>,
+    response_trailers: bool,
+    server_address: Option<String>,
+    server_port: Option<u16>,
+    network_transport: Option<NetworkTransport>,
+    request_event: bool,
+    elapsed_time: bool,
+    response_body_size: bool,
+    panic_capture: bool,
+    extension_attributes: Vec<ExtensionAttributes>,
+    operation_span: bool,
+    wait_signal: bool,
+    link_header: Option<HeaderName>,
+    default_scheme: Option<Scheme>,
+    propagation: bool,
+    inject_mode: InjectMode,
+    propagator: Option<Propagator>,
+    sampled_attribute: bool,
+    error_events: bool,
+}
+
+impl Config {
+    fn new(level: Level, kind: SpanKind) -> Self {
+        HttpTraceConfig {
+            level,
+            kind,
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl From<HttpTraceConfig> for Config {
+    fn from(config: HttpTraceConfig) -> Self {
+        Self {
+            level: config.level,
+            kind: config.kind,
+            route_attributes: config.route_attributes,
+            route_normalizer: None,
+            url_query: if config.url_query {
+                UrlQuery::Record
+            } else {
+                UrlQuery::Skip
+            },
+            header_policy: config.header_policy,
+            non_utf8_header_value: config.non_utf8_header_value,
+            header_format: config.header_format,
+            max_header_value_len: config.max_header_value_len,
+            attributes: Arc::from(config.attributes),
+            response_trailers: config.response_trailers,
+            server_address: config.server_address,
+            server_port: config.server_port,
+            network_transport: config.network_transport,
+            request_event: config.request_event,
+            elapsed_time: config.elapsed_time,
+            response_body_size: config.response_body_size,
+            panic_capture: config.panic_capture,
+            extension_attributes: Vec::new(),
+            operation_span: config.operation_span,
+            wait_signal: config.wait_signal,
+            link_header: config.link_header,
+            default_scheme: config.default_scheme,
+            propagation: config.propagation,
+            inject_mode: config.inject_mode,
+            propagator: None,
+            sampled_attribute: config.sampled_attribute,
+            error_events: config.error_events,
+        }
+    }
+}
+
+/// Options of the [`HttpLayer`], it can be used to configure the layer in a single place (e.g.
+/// from a configuration file) with [`HttpLayer::from_config`].
+///
+/// The options taking a function (the redaction of the URL query, the normalization of the route
+/// and the attributes read from the extensions) can be set only by the [`HttpLayerBuilder`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct HttpTraceConfig {
+    /// Level of the [`Span`]s, by default [`Level::INFO`].
+    pub level: Level,
+    /// Kind of the [`Span`]s, by default [`SpanKind::Server`].
+    pub kind: SpanKind,
+    /// See [`HttpLayerBuilder::route_attributes`].
+    pub route_attributes: RouteAttributes,
+    /// See [`HttpLayerBuilder::url_query`].
+    pub url_query: bool,
+    /// See [`HttpLayerBuilder::header_policy`].
+    pub header_policy: HeaderPolicy,
+    /// See [`HttpLayerBuilder::non_utf8_header_value`].
+    pub non_utf8_header_value: NonUtf8HeaderValue,
+    /// See [`HttpLayerBuilder::header_format`].
+    pub header_format: HeaderFormat,
+    /// See [`HttpLayerBuilder::max_header_value_len`].
+    pub max_header_value_len: Option<usize>,
+    /// See [`HttpLayerBuilder::attributes`].
+    pub attributes: Vec<KeyValue>,
+    /// See [`HttpLayerBuilder::response_trailers`].
+    pub response_trailers: bool,
+    /// See [`HttpLayerBuilder::server_address`].
+    pub server_address: Option<String>,
+    /// See [`HttpLayerBuilder::server_port`].
+    pub server_port: Option<u16>,
+    /// See [`HttpLayerBuilder::network_transport`].
+    pub network_transport: Option<NetworkTransport>,
+    /// See [`HttpLayerBuilder::request_event`].
+    pub request_event: bool,
+    /// See [`HttpLayerBuilder::elapsed_time`].
+    pub elapsed_time: bool,
+    /// See [`HttpLayerBuilder::response_body_size`].
+    pub response_body_size: bool,
+    /// See [`HttpLayerBuilder::panic_capture`].
+    pub panic_capture: bool,
+    /// See [`HttpLayerBuilder::operation_span`].
+    pub operation_span: bool,
+    /// See [`HttpLayerBuilder::wait_signal`].
+    pub wait_signal: bool,
+    /// See [`HttpLayerBuilder::link_header`].
+    pub link_header: Option<HeaderName>,
+    /// See [`HttpLayerBuilder::default_scheme`].
+    pub default_scheme: Option<Scheme>,
+    /// See [`HttpLayerBuilder::propagation`].
+    pub propagation: bool,
+    /// See [`HttpLayerBuilder::inject_mode`].
+    pub inject_mode: InjectMode,
+    /// See [`HttpLayerBuilder::sampled_attribute`].
+    pub sampled_attribute: bool,
+    /// See [`HttpLayerBuilder::error_events`].
+    pub error_events: bool,
+}
+
+impl Default for HttpTraceConfig {
+    fn default() -> Self {
+        Self {
+            level: Level::INFO,
+            kind: SpanKind::default(),
+            route_attributes: RouteAttributes::default(),
+            url_query: true,
+            header_policy: HeaderPolicy::default(),
+            non_utf8_header_value: NonUtf8HeaderValue::default(),
+            header_format: HeaderFormat::default(),
+            max_header_value_len: None,
+            attributes: Vec::new(),
+            response_trailers: false,
+            server_address: None,
+            server_port: None,
+            network_transport: None,
+            request_event: false,
+            elapsed_time: false,
+            response_body_size: false,
+            panic_capture: false,
+            operation_span: false,
+            wait_signal: false,
+            link_header: None,
+            default_scheme: None,
+            propagation: true,
+            inject_mode: InjectMode::default(),
+            sampled_attribute: false,
+            error_events: false,
+        }
+    }
+}
+
+/// [`Layer`] that adds tracing to a [`Service`] that handles HTTP requests.
+#[derive(Clone, Debug)]
+pub struct HttpLayer {
+    config: Arc<Config>,
 }
 
 impl HttpLayer {
     /// [`Span`]s are constructed at the given level from server side.
+    ///
+    /// The parent of the [`Span`] is the remote context extracted from the request headers, if
+    /// the request does not carry a valid remote context, a [`Span`] inserted in the request
+    /// extensions (e.g. a span started in the connection accept loop) is used as parent.
+    ///
+    /// The parent is set before the sampling decision is taken, so the sampled flag of the remote
+    /// context is honored when the tracer is configured with a parent based sampler (the default
+    /// of the OpenTelemetry SDK).
     pub fn server(level: Level) -> Self {
         Self {
-            level,
-            kind: SpanKind::Server,
+            config: Arc::new(Config::new(level, SpanKind::Server)),
         }
     }
 
     /// [`Span`]s are constructed at the given level from client side.
     pub fn client(level: Level) -> Self {
         Self {
-            level,
-            kind: SpanKind::Client,
+            config: Arc::new(Config::new(level, SpanKind::Client)),
+        }
+    }
+
+    /// The layer is configured with the given options.
+    pub fn from_config(config: HttpTraceConfig) -> Self {
+        Self {
+            config: Arc::new(config.into()),
+        }
+    }
+
+    /// [`Span`]s are constructed at the [`Level::INFO`] from server side.
+    pub fn server_default() -> Self {
+        Self::server(Level::INFO)
+    }
+
+    /// [`Span`]s are constructed at the [`Level::DEBUG`] from client side.
+    pub fn client_default() -> Self {
+        Self::client(Level::DEBUG)
+    }
+
+    /// Returns a builder to configure the layer, by default [`Span`]s are constructed at the
+    /// [`Level::INFO`] from server side.
+    pub fn builder() -> HttpLayerBuilder {
+        HttpLayerBuilder {
+            config: Config::new(Level::INFO, SpanKind::Server),
+        }
+    }
+}
+
+/// Builder of [`HttpLayer`].
+#[derive(Clone, Debug)]
+pub struct HttpLayerBuilder {
+    config: Config,
+}
+
+impl HttpLayerBuilder {
+    /// [`Span`]s are constructed from server side.
+    pub fn server(mut self) -> Self {
+        self.config.kind = SpanKind::Server;
+        self
+    }
+
+    /// [`Span`]s are constructed from client side.
+    pub fn client(mut self) -> Self {
+        self.config.kind = SpanKind::Client;
+        self
+    }
+
+    /// [`Span`]s are constructed at the given level.
+    pub fn level(mut self, level: Level) -> Self {
+        self.config.level = level;
+        self
+    }
+
+    /// Selects the attributes used to record the matched route, by default both `http.route` and
+    /// `url.template` are recorded.
+    ///
+    /// The route is available only from server side, when the `axum` feature is enabled and the
+    /// request is handled by an [`axum::Router`].
+    ///
+    /// [`axum::Router`]: https://docs.rs/axum/latest/axum/struct.Router.html
+    pub fn route_attributes(mut self, route_attributes: RouteAttributes) -> Self {
+        self.config.route_attributes = route_attributes;
+        self
+    }
+
+    /// When the matched route is not available, the route is synthesized passing the path of the
+    /// request through the given function, by default the route is not recorded.
+    ///
+    /// The function must collapse the variable segments of the path (e.g. the identifiers),
+    /// otherwise the cardinality of `http.route` is unbounded.
+    pub fn route_normalizer<F>(mut self, normalize: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.config.route_normalizer = Some(RouteNormalizer(Arc::new(normalize)));
+        self
+    }
+
+    /// When the matched route is not available, the route is synthesized from the path of the
+    /// request using [`normalize_path`].
+    pub fn normalized_route(self) -> Self {
+        self.route_normalizer(normalize_path)
+    }
+
+    /// Enables or disables the recording of the URL query, by default the query is recorded.
+    ///
+    /// Query strings frequently contain tokens and personal information, when disabled the query
+    /// is removed from both `url.query` and `url.full`.
+    pub fn url_query(mut self, enabled: bool) -> Self {
+        self.config.url_query = if enabled {
+            UrlQuery::Record
+        } else {
+            UrlQuery::Skip
+        };
+        self
+    }
+
+    /// The URL query is passed through the given function before being recorded in `url.query`
+    /// and `url.full`, it can be used to scrub sensitive parameters.
+    pub fn url_query_redaction<F>(mut self, redact: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.config.url_query = UrlQuery::Redact(Arc::new(redact));
+        self
+    }
+
+    /// Selects the headers recorded as `http.{request,response}.header.*`, by default all the
+    /// headers are recorded.
+    ///
+    /// When the request passes through several tracing layers, the headers should be recorded
+    /// only by one of them, using [`HeaderPolicy::Off`] on the others.
+    ///
+    /// The `Content-Type` of the response is always recorded as
+    /// `http.response.header.content-type`, regardless of the policy and of the format.
+    pub fn header_policy(mut self, header_policy: HeaderPolicy) -> Self {
+        self.config.header_policy = header_policy;
+        self
+    }
+
+    /// Selects how the headers values that are not valid UTF-8 are recorded, by default they are
+    /// skipped.
+    pub fn non_utf8_header_value(mut self, non_utf8_header_value: NonUtf8HeaderValue) -> Self {
+        self.config.non_utf8_header_value = non_utf8_header_value;
+        self
+    }
+
+    /// Selects how the headers are recorded, by default each header is a separate attribute.
+    ///
+    /// The `Content-Type` of the response is recorded as a separate attribute also with
+    /// [`HeaderFormat::Json`], see [`HttpLayerBuilder::header_policy`].
+    pub fn header_format(mut self, header_format: HeaderFormat) -> Self {
+        self.config.header_format = header_format;
+        self
+    }
+
+    /// The recorded header values are truncated to the given length in bytes, marking the
+    /// truncation with `...`, by default the values are not truncated.
+    ///
+    /// It bounds the size of the [`Span`] when the request carries very long values (e.g. the
+    /// `Cookie` header).
+    pub fn max_header_value_len(mut self, max_header_value_len: usize) -> Self {
+        self.config.max_header_value_len = Some(max_header_value_len);
+        self
+    }
+
+    /// The given attributes are recorded in each [`Span`], e.g. the `service.namespace` of the
+    /// logical service when several services are hosted by the same process.
+    pub fn attributes(mut self, attributes: Vec<KeyValue>) -> Self {
+        self.config.attributes = Arc::from(attributes);
+        self
+    }
+
+    /// Enables or disables the recording of the response trailers as `http.response.trailer.*`,
+    /// by default the trailers are not recorded.
+    ///
+    /// The trailers are selected using the same [`HeaderPolicy`] of the headers, they are
+    /// received at the end of the body stream, so the [`Span`] is kept alive by the
+    /// [`ResponseBody`] until then.
+    pub fn response_trailers(mut self, enabled: bool) -> Self {
+        self.config.response_trailers = enabled;
+        self
+    }
+
+    /// The given address is recorded as `server.address`, overriding the one derived from the
+    /// request.
+    ///
+    /// It can be used when the externally visible address cannot be inferred, e.g. behind a
+    /// proxy.
+    pub fn server_address(mut self, server_address: impl Into<String>) -> Self {
+        self.config.server_address = Some(server_address.into());
+        self
+    }
+
+    /// The given scheme is used from client side when the request URI does not specify it, e.g.
+    /// when the URI is in origin-form (`/path`) and the scheme is implied by the connection.
+    ///
+    /// The authority is then read from the `Host` header, so that `url.full`, `server.address` and
+    /// `server.port` can be recorded also for these requests.
+    pub fn default_scheme(mut self, default_scheme: Scheme) -> Self {
+        self.config.default_scheme = Some(default_scheme);
+        self
+    }
+
+    /// The given port is recorded as `server.port`, overriding the one derived from the request.
+    pub fn server_port(mut self, server_port: u16) -> Self {
+        self.config.server_port = Some(server_port);
+        self
+    }
+
+    /// The given transport is recorded as `network.transport`, by default it is derived from the
+    /// protocol version ([`NetworkTransport::Udp`] for HTTP/3, [`NetworkTransport::Tcp`]
+    /// otherwise).
+    ///
+    /// The transport found in the request extensions takes the precedence.
+    pub fn network_transport(mut self, network_transport: NetworkTransport) -> Self {
+        self.config.network_transport = Some(network_transport);
+        self
+    }
+
+    /// Enables or disables the `http.request.start` event, emitted inside the [`Span`] when the
+    /// request is passed to the inner service, by default the event is not emitted.
+    ///
+    /// The event is a zero-duration marker that can be used to compute the queueing latency
+    /// separately from the handler latency. The timestamp of the event is taken from the wall
+    /// clock, so it also carries `offset_ns`: the time elapsed since the creation of the [`Span`]
+    /// in nanoseconds, measured by a monotonic clock.
+    pub fn request_event(mut self, enabled: bool) -> Self {
+        self.config.request_event = enabled;
+        self
+    }
+
+    /// Enables or disables the insertion of the [`ElapsedTime`] in the response extensions, by
+    /// default it is not inserted.
+    pub fn elapsed_time(mut self, enabled: bool) -> Self {
+        self.config.elapsed_time = enabled;
+        self
+    }
+
+    /// Enables or disables the recording of the response body size as `http.response.body.size`,
+    /// by default the size is not recorded.
+    ///
+    /// The size is measured counting the bytes of the data frames, so it is accurate also for
+    /// streamed responses, the [`Span`] is kept alive by the [`ResponseBody`] until the end of the
+    /// stream.
+    ///
+    /// When the response declares the size of the uncompressed body in the
+    /// `X-Uncompressed-Length` header, it is recorded as `http.response.body.uncompressed_size`
+    /// and the ratio between the uncompressed and the transferred sizes is recorded as
+    /// `http.response.compression_ratio`.
+    pub fn response_body_size(mut self, enabled: bool) -> Self {
+        self.config.response_body_size = enabled;
+        self
+    }
+
+    /// Enables or disables the capture of the panics raised by the inner service while polling
+    /// the response future, by default the panics are not captured.
+    ///
+    /// When a panic is captured the [`Span`] is marked as failed, recording `exception.type` as
+    /// `panic` and the panic message as `exception.message`, then the unwinding is resumed. The
+    /// future is polled inside [`catch_unwind`](std::panic::catch_unwind), this has a cost on each
+    /// poll and assumes that the inner future is unwind safe: it must not be polled again after
+    /// the panic, since its state could be broken.
+    pub fn panic_capture(mut self, enabled: bool) -> Self {
+        self.config.panic_capture = enabled;
+        self
+    }
+
+    /// The attributes of the extension `T` found in the request are recorded in the [`Span`].
+    ///
+    /// It can be used to record attributes known by another middleware, e.g. the `enduser.id` and
+    /// `enduser.role` of the authenticated user. The extension is read when the request is
+    /// received, so the middleware inserting it must be applied before this layer.
+    pub fn extension_attributes<T>(mut self) -> Self
+    where
+        T: Clone + Into<Vec<KeyValue>> + Send + Sync + 'static,
+    {
+        let extension_attributes = ExtensionAttributes(Arc::new(|extensions: &Extensions| {
+            extensions
+                .get::<T>()
+                .cloned()
+                .map(Into::into)
+                .unwrap_or_default()
+        }));
+        self.config.extension_attributes.push(extension_attributes);
+        self
+    }
+
+    /// Enables or disables the creation of a [`Span`] for the logical operation, by default it is
+    /// disabled.
+    ///
+    /// It is used from client side to group the attempts of a retried request: this layer must
+    /// sit above the retry layer, it creates the [`Span`] of the operation and inserts it in the
+    /// request extensions as [`OperationSpan`], without injecting the propagation headers. The
+    /// [`Span`] of the operation is an internal one. Another [`HttpLayer`] must sit below the
+    /// retry layer, it creates a child client [`Span`] for each attempt, recording
+    /// `http.request.resend_count` for the retries. The retry policy must preserve the
+    /// extensions when cloning the request.
+    pub fn operation_span(mut self, enabled: bool) -> Self {
+        self.config.operation_span = enabled;
+        self
+    }
+
+    /// Enables or disables the insertion of the [`WaitSignal`] in the request extensions, by
+    /// default it is not inserted.
+    ///
+    /// It is meaningful only from server side, for long-poll endpoints: the handler calls
+    /// [`WaitSignal::ready`] when the awaited event occurs, the waiting time is recorded as
+    /// `http.server.wait.duration` and it can be subtracted from the duration of the [`Span`].
+    pub fn wait_signal(mut self, enabled: bool) -> Self {
+        self.config.wait_signal = enabled;
+        self
+    }
+
+    /// The [`Span`] is linked to the contexts found in the given header of the request, e.g.
+    /// `link-traceparent`.
+    ///
+    /// Each value of the header is parsed as a W3C `traceparent`, regardless of the propagator used
+    /// to extract the parent context (e.g. also when only `b3` is propagated), the invalid values
+    /// are skipped. It can be used by batch or queue consumers to link the producers of the
+    /// messages.
+    pub fn link_header(mut self, link_header: HeaderName) -> Self {
+        self.config.link_header = Some(link_header);
+        self
+    }
+
+    /// Enables or disables the injection of the context in the request headers from client side,
+    /// by default the context is injected.
+    ///
+    /// It can be disabled to avoid leaking the propagation headers to untrusted services.
+    ///
+    /// When an [`opentelemetry::Context`] is found in the request extensions, the client [`Span`]
+    /// is its child, so that the requests built outside of `tracing` are propagated in the trace
+    /// of the given context.
+    pub fn propagation(mut self, enabled: bool) -> Self {
+        self.config.propagation = enabled;
+        self
+    }
+
+    /// Selects how the context is injected in the request headers from client side, by default
+    /// the existing propagation headers are overwritten.
+    pub fn inject_mode(mut self, inject_mode: InjectMode) -> Self {
+        self.config.inject_mode = inject_mode;
+        self
+    }
+
+    /// Sets the propagator used to extract and inject the context, in place of the global one.
+    ///
+    /// It avoids the mutation of the global state, e.g. in the tests or when the services of a
+    /// process use different propagation formats.
+    pub fn propagator<P>(mut self, propagator: P) -> Self
+    where
+        P: TextMapPropagator + Send + Sync + 'static,
+    {
+        self.config.propagator = Some(Propagator(Arc::new(propagator)));
+        self
+    }
+
+    /// Enables or disables the recording of the sampling decision as `otel.sampled`, by default
+    /// it is not recorded.
+    ///
+    /// The sampled flag is read after the parent is set, so it reflects the decision taken by the
+    /// local sampler, it helps diagnosing the traces missing from the backend (e.g. with a tail
+    /// sampling collector receiving also the unsampled spans).
+    pub fn sampled_attribute(mut self, enabled: bool) -> Self {
+        self.config.sampled_attribute = enabled;
+        self
+    }
+
+    /// Enables or disables the emission of an event at the [`Level::ERROR`] when the inner service
+    /// fails, by default the event is not emitted.
+    ///
+    /// The event is emitted inside the [`Span`] and it carries the `error.message` and the
+    /// `error.type`, so that the failure is visible also in the logs.
+    pub fn error_events(mut self, enabled: bool) -> Self {
+        self.config.error_events = enabled;
+        self
+    }
+
+    /// Returns the configured [`HttpLayer`].
+    pub fn build(self) -> HttpLayer {
+        HttpLayer {
+            config: Arc::new(self.config),
         }
     }
 }
@@ -56,44 +681,103 @@ impl<S> Layer<S> for HttpLayer {
     fn layer(&self, inner: S) -> Self::Service {
         Http {
             inner,
-            level: self.level,
-            kind: self.kind,
+            config: self.config.clone(),
+            pending_since: None,
         }
     }
 }
 
 /// Middleware that adds tracing to a [`Service`] that handles HTTP requests.
-#[derive(Clone, Debug)]
+///
+/// From client side, the time spent waiting for the inner service to be ready is recorded as
+/// `http.client.poll_ready.duration` (in seconds).
+///
+/// The inner service is not required to be [`Clone`], the middleware is cloneable only when the
+/// inner service is.
+#[derive(Debug)]
 pub struct Http<S> {
     inner: S,
-    level: Level,
-    kind: SpanKind,
+    config: Arc<Config>,
+    pending_since: Option<Instant>,
+}
+
+impl<S: Clone> Clone for Http<S> {
+    fn clone(&self) -> Self {
+        // the readiness is not shared by the clones
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            pending_since: None,
+        }
+    }
+}
+
+impl<S> Http<S> {
+    /// Wraps the given service, it is equivalent to [`HttpLayer::server`].
+    pub fn new_server(inner: S, level: Level) -> Self {
+        HttpLayer::server(level).layer(inner)
+    }
+
+    /// Wraps the given service, it is equivalent to [`HttpLayer::client`].
+    pub fn new_client(inner: S, level: Level) -> Self {
+        HttpLayer::client(level).layer(inner)
+    }
 }
 
 impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Http<S>
 where
     S: Service<Request<ReqBody>, Response = Response<ResBody>>,
-    S::Error: Display,
+    S::Error: Display + 'static,
+    ReqBody: Body,
+    ResBody: Body,
 {
-    type Response = S::Response;
+    type Response = Response<ResponseBody<ResBody>>;
     type Error = S::Error;
     type Future = ResponseFuture<S::Future>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.inner.poll_ready(cx)
+        let poll = self.inner.poll_ready(cx);
+        if let (SpanKind::Client, true, None) =
+            (self.config.kind, poll.is_pending(), self.pending_since)
+        {
+            self.pending_since = Some(Instant::now());
+        }
+        poll
     }
 
     fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
-        let span = make_request_span(self.level, self.kind, &mut req);
+        let span_start = self.config.request_event.then(Instant::now);
+        let span = make_request_span(&self.config, &mut req);
+        if let Some(pending_since) = self.pending_since.take() {
+            let duration = pending_since.elapsed().as_secs_f64();
+            span.record("http.client.poll_ready.duration", duration);
+        }
+        if !span.is_disabled() {
+            req.extensions_mut().insert(span.context());
+        }
+        if let (SpanKind::Server, true) = (self.config.kind, self.config.wait_signal) {
+            let wait_signal = WaitSignal {
+                span: span.clone(),
+                start: Instant::now(),
+            };
+            req.extensions_mut().insert(wait_signal);
+        }
+        let start = self.config.elapsed_time.then(Instant::now);
+        let method = req.method().clone();
         let inner = {
             let _enter = span.enter();
+            if let Some(span_start) = span_start {
+                emit_request_event(&self.config, span_start);
+            }
             self.inner.call(req)
         };
 
         ResponseFuture {
             inner,
             span,
-            kind: self.kind,
+            config: self.config.clone(),
+            method,
+            start,
         }
     }
 }
@@ -104,58 +788,273 @@ pub struct ResponseFuture<F> {
     #[pin]
     inner: F,
     span: Span,
-    kind: SpanKind,
+    config: Arc<Config>,
+    method: Method,
+    start: Option<Instant>,
 }
 
 impl<F, ResBody, E> Future for ResponseFuture<F>
 where
     F: Future<Output = Result<Response<ResBody>, E>>,
-    E: Display,
+    ResBody: Body,
+    E: Display + 'static,
 {
-    type Output = Result<Response<ResBody>, E>;
+    type Output = Result<Response<ResponseBody<ResBody>>, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
         let _enter = this.span.enter();
 
-        match ready!(this.inner.poll(cx)) {
-            Ok(response) => {
-                record_response(this.span, *this.kind, &response);
+        let poll = if this.config.panic_capture {
+            let inner = this.inner;
+            match panic::catch_unwind(AssertUnwindSafe(|| inner.poll(cx))) {
+                Ok(poll) => poll,
+                Err(payload) => {
+                    record_panic(this.span, payload.as_ref());
+                    panic::resume_unwind(payload)
+                }
+            }
+        } else {
+            this.inner.poll(cx)
+        };
+
+        match ready!(poll) {
+            Ok(mut response) => {
+                record_response(this.span, this.config, this.method, &response);
+                if let Some(start) = this.start {
+                    let elapsed_time = ElapsedTime(start.elapsed());
+                    response.extensions_mut().insert(elapsed_time);
+                }
+                let config = this.config.clone();
+                let span = (config.response_trailers || config.response_body_size)
+                    .then(|| this.span.clone());
+                let uncompressed_size = config
+                    .response_body_size
+                    .then(|| uncompressed_size(&response))
+                    .flatten();
+                if let Some(uncompressed_size) = uncompressed_size {
+                    this.span.record(
+                        "http.response.body.uncompressed_size",
+                        uncompressed_size as i64,
+                    );
+                }
+                let body_size = config.response_body_size.then_some(0);
+                let response = response.map(|inner| ResponseBody {
+                    inner,
+                    span,
+                    config,
+                    body_size,
+                    uncompressed_size,
+                });
                 Poll::Ready(Ok(response))
             }
             Err(err) => {
-                record_error(this.span, &err);
+                let event_target = this
+                    .config
+                    .error_events
+                    .then(|| span_target(this.config.kind));
+                record_error(this.span, &err, event_target);
                 Poll::Ready(Err(err))
             }
         }
     }
 }
 
-/// String representation of HTTP method
-fn http_method(method: &Method) -> Option<&'static str> {
-    match *method {
-        Method::GET => Some("GET"),
-        Method::POST => Some("POST"),
-        Method::PUT => Some("PUT"),
-        Method::DELETE => Some("DELETE"),
-        Method::HEAD => Some("HEAD"),
-        Method::OPTIONS => Some("OPTIONS"),
-        Method::CONNECT => Some("CONNECT"),
-        Method::PATCH => Some("PATCH"),
-        Method::TRACE => Some("TRACE"),
-        _ => None,
+/// Response body for [`Http`].
+///
+/// When the recording of the trailers or of the body size is enabled, the body keeps the [`Span`]
+/// alive until the end of the stream.
+#[pin_project]
+pub struct ResponseBody<B> {
+    #[pin]
+    inner: B,
+    span: Option<Span>,
+    config: Arc<Config>,
+    /// Length of the data frames, counted only if the body size is recorded.
+    body_size: Option<u64>,
+    uncompressed_size: Option<u64>,
+}
+
+impl<B: Body> Body for ResponseBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let frame = ready!(this.inner.as_mut().poll_frame(cx));
+
+        if let Some(span) = this.span {
+            if let (true, Some(Ok(frame))) = (this.config.response_trailers, &frame) {
+                if let Some(trailers) = frame.trailers_ref() {
+                    record_http_headers(
+                        span,
+                        this.config,
+                        "http.response.trailer",
+                        "http.response.trailers",
+                        trailers,
+                    );
+                }
+            }
+            if let (Some(size), Some(Ok(frame))) = (this.body_size.as_mut(), &frame) {
+                if let Some(data) = frame.data_ref() {
+                    *size += data.remaining() as u64;
+                }
+            }
+            if frame.is_none() || this.inner.is_end_stream() {
+                if let Some(size) = this.body_size.take() {
+                    span.record("http.response.body.size", size as i64);
+                    if let (Some(uncompressed_size), true) = (this.uncompressed_size, size > 0) {
+                        let compression_ratio = *uncompressed_size as f64 / size as f64;
+                        span.record("http.response.compression_ratio", compression_ratio);
+                    }
+                }
+            }
+        }
+
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
     }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Size of the uncompressed response body, declared by the `X-Uncompressed-Length` header
+fn uncompressed_size<B>(response: &Response<B>) -> Option<u64> {
+    response
+        .headers()
+        .get("x-uncompressed-length")
+        .and_then(|header_value| header_value.to_str().ok())
+        .and_then(|uncompressed_length| uncompressed_length.trim().parse().ok())
+}
+
+/// Matched route of the request
+#[cfg(feature = "axum")]
+fn http_route<B>(request: &Request<B>) -> Option<&str> {
+    request
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|matched_path| matched_path.as_str())
+}
+
+/// Matched route of the request
+#[cfg(not(feature = "axum"))]
+fn http_route<B>(_request: &Request<B>) -> Option<&str> {
+    None
+}
+
+/// Replaces the numeric and UUID segments of the path with `{id}`.
+///
+/// It is the normalizer used by [`HttpLayerBuilder::normalized_route`], e.g. the path
+/// `/users/42/posts/67e55044-10b1-426f-9247-bb680e5fe0c8` is normalized as
+/// `/users/{id}/posts/{id}`.
+pub fn normalize_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            if is_numeric(segment) || is_uuid(segment) {
+                "{id}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Checks if the segment is a non-empty sequence of digits
+fn is_numeric(segment: &str) -> bool {
+    !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// Checks if the segment is an UUID in the hyphenated form
+fn is_uuid(segment: &str) -> bool {
+    segment.len() == 36
+        && segment
+            .bytes()
+            .enumerate()
+            .all(|(index, byte)| match index {
+                8 | 13 | 18 | 23 => byte == b'-',
+                _ => byte.is_ascii_hexdigit(),
+            })
 }
 
-/// String representation of network protocol version
-fn http_version(version: Version) -> Option<&'static str> {
+/// Transport protocol used by the given protocol version
+fn network_transport(version: Version) -> NetworkTransport {
     match version {
-        Version::HTTP_09 => Some("0.9"),
-        Version::HTTP_10 => Some("1.0"),
-        Version::HTTP_11 => Some("1.1"),
-        Version::HTTP_2 => Some("2"),
-        Version::HTTP_3 => Some("3"),
-        _ => None,
+        Version::HTTP_3 => NetworkTransport::Udp,
+        _ => NetworkTransport::Tcp,
+    }
+}
+
+/// Scheme of the request URL
+///
+/// The scheme of the URI is used first (from server side it is available for HTTP/2 requests
+/// from the `:scheme` pseudo-header), then from server side the `Forwarded` and
+/// `X-Forwarded-Proto` headers are used (HTTP/1 requests behind a proxy). Otherwise the server
+/// falls back to `https` when the [`TlsInfo`] extension is present, and to `http` if not.
+fn url_scheme<B>(kind: SpanKind, request: &Request<B>) -> Option<&str> {
+    if let Some(scheme) = request.uri().scheme_str() {
+        return Some(scheme);
+    }
+    if let SpanKind::Client = kind {
+        return None;
+    }
+
+    let headers = request.headers();
+    let forwarded_proto = headers
+        .get(http::header::FORWARDED)
+        .and_then(|header_value| header_value.to_str().ok())
+        .and_then(|forwarded| {
+            let forwarded = forwarded.split(',').next()?;
+            forwarded.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("proto")
+                    .then(|| value.trim_matches('"'))
+            })
+        });
+    let forwarded_proto = forwarded_proto.or_else(|| {
+        headers
+            .get("x-forwarded-proto")
+            .and_then(|header_value| header_value.to_str().ok())
+            .and_then(|proto| proto.split(',').next())
+            .map(str::trim)
+    });
+    match forwarded_proto {
+        Some(proto) => Some(proto),
+        None if request.extensions().get::<TlsInfo>().is_some() => Some("https"),
+        None => Some("http"),
+    }
+}
+
+/// Emits the event marking the start of the request, with the monotonic time elapsed since the
+/// creation of the [`Span`].
+///
+/// The event has the same target of the [`Span`], so that they are filtered together.
+fn emit_request_event(config: &Config, span_start: Instant) {
+    let offset_ns = span_start.elapsed().as_nanos() as i64;
+    macro_rules! emit_event {
+        ($level:expr, $target:expr) => {
+            tracing::event!(target: $target, $level, offset_ns, "http.request.start")
+        };
+    }
+    match (config.level, config.kind) {
+        (Level::ERROR, SpanKind::Client) => emit_event!(Level::ERROR, CLIENT_TARGET),
+        (Level::WARN, SpanKind::Client) => emit_event!(Level::WARN, CLIENT_TARGET),
+        (Level::INFO, SpanKind::Client) => emit_event!(Level::INFO, CLIENT_TARGET),
+        (Level::DEBUG, SpanKind::Client) => emit_event!(Level::DEBUG, CLIENT_TARGET),
+        (Level::TRACE, SpanKind::Client) => emit_event!(Level::TRACE, CLIENT_TARGET),
+        (Level::ERROR, SpanKind::Server) => emit_event!(Level::ERROR, SERVER_TARGET),
+        (Level::WARN, SpanKind::Server) => emit_event!(Level::WARN, SERVER_TARGET),
+        (Level::INFO, SpanKind::Server) => emit_event!(Level::INFO, SERVER_TARGET),
+        (Level::DEBUG, SpanKind::Server) => emit_event!(Level::DEBUG, SERVER_TARGET),
+        (Level::TRACE, SpanKind::Server) => emit_event!(Level::TRACE, SERVER_TARGET),
     }
 }
 
@@ -167,83 +1066,310 @@ fn span_kind(kind: SpanKind) -> &'static str {
     }
 }
 
+/// Target of the [`Span`]s of the given kind.
+fn span_target(kind: SpanKind) -> &'static str {
+    match kind {
+        SpanKind::Client => CLIENT_TARGET,
+        SpanKind::Server => SERVER_TARGET,
+    }
+}
+
+/// Target of the client [`Span`]s.
+pub(crate) const CLIENT_TARGET: &str = "tower_otel::trace::http::client";
+
+/// Target of the server [`Span`]s.
+pub(crate) const SERVER_TARGET: &str = "tower_otel::trace::http::server";
+
 /// Creates a new [`Span`] for the given request.
-fn make_request_span<B>(level: Level, kind: SpanKind, request: &mut Request<B>) -> Span {
+fn make_request_span<B: Body>(config: &Config, request: &mut Request<B>) -> Span {
+    let kind = config.kind;
+    let network_transport = request
+        .extensions()
+        .get::<NetworkTransport>()
+        .copied()
+        .or(config.network_transport)
+        .unwrap_or_else(|| network_transport(request.version()));
+    // The span of the logical operation is not a remote call, the attempts are.
+    let otel_kind = match kind {
+        SpanKind::Client if config.operation_span => "internal",
+        kind => span_kind(kind),
+    };
     macro_rules! make_span {
-        ($level:expr) => {{
+        ($level:expr, $target:expr) => {{
             use tracing::field::Empty;
 
             tracing::span!(
+                target: $target,
                 $level,
                 "HTTP",
+                "client.address" = Empty,
+                "client.port" = Empty,
+                "code.function.name" = Empty,
                 "error.message" = Empty,
+                "error.type" = Empty,
+                "exception.message" = Empty,
+                "exception.type" = Empty,
+                "http.client.poll_ready.duration" = Empty,
+                "http.request.body.size" = http_request_size(request).map(|size| size as i64),
                 "http.request.method" = http_method(request.method()),
+                "http.request.method_original" = Empty,
+                "http.request.resend_count" = Empty,
+                "http.response.body.size" = Empty,
+                "http.response.body.uncompressed_size" = Empty,
+                "http.response.compression_ratio" = Empty,
                 "http.response.status_code" = Empty,
+                "http.route" = Empty,
+                "http.server.wait.duration" = Empty,
                 "network.protocol.name" = "http",
                 "network.protocol.version" = http_version(request.version()),
-                "otel.kind" = span_kind(kind),
+                "network.transport" = network_transport.as_str(),
+                "otel.kind" = otel_kind,
+                "otel.name" = Empty,
                 "otel.status_code" = Empty,
+                "otel.status_message" = Empty,
+                "server.address" = Empty,
+                "server.port" = Empty,
+                "tls.cipher" = Empty,
+                "tls.protocol.version" = Empty,
                 "url.full" = Empty,
                 "url.path" = request.uri().path(),
                 "url.query" = Empty,
+                "url.scheme" = Empty,
+                "url.template" = Empty,
             )
         }};
     }
 
-    let span = match level {
-        Level::ERROR => make_span!(Level::ERROR),
-        Level::WARN => make_span!(Level::WARN),
-        Level::INFO => make_span!(Level::INFO),
-        Level::DEBUG => make_span!(Level::DEBUG),
-        Level::TRACE => make_span!(Level::TRACE),
+    let span = match (config.level, kind) {
+        (Level::ERROR, SpanKind::Client) => make_span!(Level::ERROR, CLIENT_TARGET),
+        (Level::WARN, SpanKind::Client) => make_span!(Level::WARN, CLIENT_TARGET),
+        (Level::INFO, SpanKind::Client) => make_span!(Level::INFO, CLIENT_TARGET),
+        (Level::DEBUG, SpanKind::Client) => make_span!(Level::DEBUG, CLIENT_TARGET),
+        (Level::TRACE, SpanKind::Client) => make_span!(Level::TRACE, CLIENT_TARGET),
+        (Level::ERROR, SpanKind::Server) => make_span!(Level::ERROR, SERVER_TARGET),
+        (Level::WARN, SpanKind::Server) => make_span!(Level::WARN, SERVER_TARGET),
+        (Level::INFO, SpanKind::Server) => make_span!(Level::INFO, SERVER_TARGET),
+        (Level::DEBUG, SpanKind::Server) => make_span!(Level::DEBUG, SERVER_TARGET),
+        (Level::TRACE, SpanKind::Server) => make_span!(Level::TRACE, SERVER_TARGET),
+    };
+
+    // The span is filtered out, neither the attributes nor the context are worth the cost
+    // of recording or propagating them.
+    if span.is_disabled() {
+        return span;
+    }
+
+    record_http_headers(
+        &span,
+        config,
+        "http.request.header",
+        "http.request.headers",
+        request.headers(),
+    );
+
+    for attribute in config.attributes.iter() {
+        span.set_attribute(attribute.key.clone(), attribute.value.clone());
+    }
+
+    for ExtensionAttributes(extension_attributes) in &config.extension_attributes {
+        for attribute in extension_attributes(request.extensions()) {
+            span.set_attribute(attribute.key, attribute.value);
+        }
+    }
+
+    let query = match (&config.url_query, request.uri().query()) {
+        (UrlQuery::Record, Some(query)) => Some(Cow::Borrowed(query)),
+        (UrlQuery::Redact(redact), Some(query)) => Some(Cow::Owned(redact(query))),
+        _ => None,
+    };
+    if let Some(query) = &query {
+        span.record("url.query", query.as_ref());
+    }
+
+    let route = match (http_route(request), &config.route_normalizer) {
+        (Some(route), _) => Some(Cow::Borrowed(route)),
+        (None, Some(RouteNormalizer(normalize))) => {
+            Some(Cow::Owned(normalize(request.uri().path())))
+        }
+        (None, None) => None,
     };
+    if let Some(route) = route.as_deref() {
+        if config.route_attributes != RouteAttributes::UrlTemplate {
+            span.record("http.route", route);
+        }
+        if config.route_attributes != RouteAttributes::HttpRoute {
+            span.record("url.template", route);
+        }
+    }
 
-    for (header_name, header_value) in request.headers().iter() {
-        if let Ok(attribute_value) = header_value.to_str() {
-            let attribute_name = format!("http.request.header.{}", header_name);
-            span.set_attribute(attribute_name, attribute_value.to_owned());
+    let method = match http_method(request.method()) {
+        "_OTHER" => {
+            span.record("http.request.method_original", request.method().as_str());
+            "HTTP"
         }
+        method => method,
+    };
+    match route {
+        Some(route) => span.record("otel.name", format!("{} {}", method, route)),
+        None => span.record("otel.name", method),
+    };
+
+    let url_scheme = url_scheme(kind, request).or_else(|| match kind {
+        SpanKind::Client => config.default_scheme.as_ref().map(Scheme::as_str),
+        SpanKind::Server => None,
+    });
+    if let Some(url_scheme) = url_scheme {
+        span.record("url.scheme", url_scheme);
     }
 
-    if let Some(query) = request.uri().query() {
-        span.record("url.query", query);
+    let authority = request_authority(request);
+    let server_name = match kind {
+        SpanKind::Client => None,
+        SpanKind::Server => request
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(|tls_info| tls_info.server_name.as_deref()),
+    };
+    let server_address = server_name.or(authority.as_ref().map(authority_host));
+    let server_port = authority
+        .as_ref()
+        .and_then(|authority| authority_port(authority, url_scheme));
+    if let Some(server_address) = config.server_address.as_deref().or(server_address) {
+        span.record("server.address", server_address);
+    }
+    if let Some(server_port) = config.server_port.or(server_port) {
+        span.record("server.port", server_port as i64);
     }
 
     match kind {
         SpanKind::Client => {
-            span.record("url.full", tracing::field::display(request.uri()));
+            let uri = match (request.uri().scheme(), url_scheme, &authority) {
+                (None, Some(url_scheme), Some(authority)) => {
+                    format!("{}://{}{}", url_scheme, authority, request.uri().path())
+                }
+                _ => request.uri().to_string(),
+            };
+            let url = uri.split_once('?').map_or(uri.as_str(), |(url, _)| url);
+            match &query {
+                Some(query) => span.record("url.full", format!("{}?{}", url, query)),
+                None => span.record("url.full", url),
+            };
 
-            let context = span.context();
-            opentelemetry::global::get_text_map_propagator(|injector| {
-                injector.inject_context(&context, &mut HeaderInjector(request.headers_mut()));
-            });
+            if let Some(context) = request.extensions().get::<opentelemetry::Context>() {
+                if context.span().span_context().is_valid() {
+                    span.set_parent(context.clone());
+                }
+            }
+
+            if config.operation_span {
+                let operation_span = OperationSpan {
+                    span: span.clone(),
+                    attempts: Arc::new(AtomicU32::new(0)),
+                };
+                request.extensions_mut().insert(operation_span);
+            } else {
+                if let Some(operation_span) = request.extensions().get::<OperationSpan>() {
+                    span.set_parent(operation_span.span.context());
+                    let resend_count = operation_span.attempts.fetch_add(1, Ordering::Relaxed);
+                    if resend_count > 0 {
+                        span.record("http.request.resend_count", resend_count as i64);
+                    }
+                }
+
+                if config.propagation {
+                    let context = span.context();
+                    inject_context(
+                        config.propagator.as_ref(),
+                        &context,
+                        request.headers_mut(),
+                        config.inject_mode,
+                    );
+                }
+            }
         }
         SpanKind::Server => {
-            let context = opentelemetry::global::get_text_map_propagator(|extractor| {
-                extractor.extract(&HeaderExtractor(request.headers_mut()))
+            let context = with_propagator(config.propagator.as_ref(), |propagator| {
+                propagator.extract(&HeaderExtractor(request.headers_mut()))
             });
-            span.set_parent(context);
+            match request.extensions().get::<Span>() {
+                Some(parent) if !context.span().span_context().is_valid() => {
+                    span.set_parent(parent.context())
+                }
+                _ => span.set_parent(context),
+            }
+
+            if let Some((client_address, client_port)) = client_address(request) {
+                span.record("client.address", client_address);
+                if let Some(client_port) = client_port {
+                    span.record("client.port", client_port as i64);
+                }
+            }
+
+            if let Some(tls_info) = request.extensions().get::<TlsInfo>() {
+                record_tls_info(&span, tls_info);
+            }
+
+            if let Some(ConnectionSpan(connection_span)) = request.extensions().get() {
+                let connection_context = connection_span.context();
+                let span_context = connection_context.span().span_context().clone();
+                span.add_link(span_context);
+            }
+        }
+    }
+
+    if let Some(link_header) = &config.link_header {
+        for header_value in request.headers().get_all(link_header) {
+            let span_context = header_value.to_str().ok().and_then(parse_traceparent);
+            if let Some(span_context) = span_context {
+                span.add_link(span_context);
+            }
         }
     }
+    if config.sampled_attribute {
+        let sampled = span.context().span().span_context().is_sampled();
+        span.set_attribute("otel.sampled", sampled);
+    }
 
     span
 }
 
 /// Records fields associated to the response.
-fn record_response<B>(span: &Span, kind: SpanKind, response: &Response<B>) {
+fn record_response<B: Body>(span: &Span, config: &Config, method: &Method, response: &Response<B>) {
     span.record(
         "http.response.status_code",
         response.status().as_u16() as i64,
     );
 
-    for (header_name, header_value) in response.headers().iter() {
-        if let Ok(attribute_value) = header_value.to_str() {
-            let attribute_name = format!("http.response.header.{}", header_name);
-            span.set_attribute(attribute_name, attribute_value.to_owned());
+    if let Some(size) = http_response_size(method, response) {
+        span.record("http.response.body.size", size as i64);
+    }
+
+    record_http_headers(
+        span,
+        config,
+        "http.response.header",
+        "http.response.headers",
+        response.headers(),
+    );
+
+    // recorded as a separate attribute, unless it is already recorded with the other headers
+    let content_type = http::header::CONTENT_TYPE;
+    let recorded = config.header_format == HeaderFormat::Attributes
+        && config.header_policy.is_recorded(&content_type);
+    if !recorded {
+        let content_type = response
+            .headers()
+            .get(content_type)
+            .and_then(|header_value| header_value.to_str().ok());
+        if let Some(content_type) = content_type {
+            span.set_attribute(
+                "http.response.header.content-type",
+                Value::Array(Array::String(vec![content_type.to_owned().into()])),
+            );
         }
     }
 
-    if let SpanKind::Client = kind {
+    if let SpanKind::Client = config.kind {
         if response.status().is_client_error() {
             span.record("otel.status_code", "ERROR");
         }
@@ -251,10 +1377,57 @@ fn record_response<B>(span: &Span, kind: SpanKind, response: &Response<B>) {
     if response.status().is_server_error() {
         span.record("otel.status_code", "ERROR");
     }
+
+    if let Some(response_error) = response.extensions().get::<ResponseError>() {
+        span.record("otel.status_message", response_error.message.as_str());
+        span.record("error.message", response_error.message.as_str());
+        span.record("error.type", response_error.error_type.as_str());
+    }
+
+    if let Some(code_function) = response.extensions().get::<CodeFunction>() {
+        span.record("code.function.name", code_function.qualified_name());
+    }
 }
 
-/// Records the error message.
-fn record_error<E: Display>(span: &Span, err: &E) {
+/// Records the headers with the prefix, or as the JSON attribute, as selected by the config.
+fn record_http_headers(
+    span: &Span,
+    config: &Config,
+    prefix: &str,
+    json_attribute_name: &'static str,
+    headers: &HeaderMap,
+) {
+    match config.header_format {
+        HeaderFormat::Attributes => record_headers(
+            span,
+            prefix,
+            headers,
+            &config.header_policy,
+            config.non_utf8_header_value,
+            config.max_header_value_len,
+            false,
+        ),
+        HeaderFormat::Json => record_headers_json(
+            span,
+            json_attribute_name,
+            headers,
+            &config.header_policy,
+            config.non_utf8_header_value,
+            config.max_header_value_len,
+        ),
+    }
+}
+
+/// Records the panic raised by the inner service.
+fn record_panic(span: &Span, payload: &(dyn Any + Send)) {
     span.record("otel.status_code", "ERROR");
-    span.record("error.message", err.to_string());
+    span.record("exception.type", "panic");
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+    if let Some(message) = message {
+        span.record("otel.status_message", message);
+        span.record("exception.message", message);
+    }
 }
@@ -1,8 +1,9 @@
 //! Implementation of fields injector.
 
-use std::str::FromStr;
+use std::{fmt, str::FromStr, sync::Arc};
 
 use http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::propagation::TextMapPropagator;
 
 pub struct HeaderInjector<'a>(pub &'a mut HeaderMap);
 
@@ -15,3 +16,52 @@ impl<'a> opentelemetry::propagation::Injector for HeaderInjector<'a> {
         }
     }
 }
+
+/// Describes how the context is injected in the headers of the requests sent by a client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InjectMode {
+    /// The propagation headers are always written, overwriting the existing ones.
+    #[default]
+    Overwrite,
+    /// The propagation headers are written only if none of them is already present, e.g. when
+    /// a proxy forwards a request already traced by another layer.
+    IfAbsent,
+}
+
+/// Propagator of a layer, used in place of the global one.
+#[derive(Clone)]
+pub(crate) struct Propagator(pub(crate) Arc<dyn TextMapPropagator + Send + Sync>);
+
+impl fmt::Debug for Propagator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Propagator")
+    }
+}
+
+/// Calls the closure with the propagator of the layer, or with the global one if not set.
+pub(crate) fn with_propagator<T>(
+    propagator: Option<&Propagator>,
+    mut f: impl FnMut(&dyn TextMapPropagator) -> T,
+) -> T {
+    match propagator {
+        Some(Propagator(propagator)) => f(propagator.as_ref()),
+        None => opentelemetry::global::get_text_map_propagator(f),
+    }
+}
+
+/// Injects the context in the headers using the given propagator.
+pub(crate) fn inject_context(
+    propagator: Option<&Propagator>,
+    context: &opentelemetry::Context,
+    headers: &mut HeaderMap,
+    inject_mode: InjectMode,
+) {
+    with_propagator(propagator, |propagator| {
+        if let InjectMode::IfAbsent = inject_mode {
+            if propagator.fields().any(|field| headers.contains_key(field)) {
+                return;
+            }
+        }
+        propagator.inject_context(context, &mut HeaderInjector(headers));
+    });
+}
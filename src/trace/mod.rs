@@ -1,14 +1,64 @@
 //! Middleware that adds tracing to a [`Service`].
 //!
+//! # Propagation
+//!
+//! The context is extracted from the headers of the requests received by a server, and it is
+//! injected in the headers of the requests sent by a client, using the global
+//! [`TextMapPropagator`]. Any propagator can be used, e.g. the W3C `traceparent` propagator
+//! provided by `opentelemetry_sdk`, or the `b3` and `jaeger` propagators (provided by
+//! `opentelemetry-zipkin` and `opentelemetry-jaeger`) for legacy infrastructures. Several formats
+//! can be accepted and produced at the same time registering a [`TextMapCompositePropagator`]
+//! with [`set_text_map_propagator`]. A propagator can also be set on a single layer (e.g.
+//! [`HttpLayerBuilder::propagator`]), without mutating the global state.
+//!
+//! # Targets
+//!
+//! The [`Span`]s, and the events emitted inside them, are created with distinct targets, so that
+//! they can be filtered by protocol and kind, e.g. with the `EnvFilter` directive `tower_otel::trace::http=debug` or
+//! `tower_otel::trace::grpc::client=off`:
+//!
+//! - `tower_otel::trace::http::server` and `tower_otel::trace::http::client` for [`HttpLayer`];
+//! - `tower_otel::trace::grpc::server` and `tower_otel::trace::grpc::client` for [`GrpcLayer`];
+//! - `tower_otel::trace::connection` for [`ConnectionLayer`].
+//!
+//! When a [`Span`] is filtered out, the headers are not recorded and the context is not
+//! propagated, so the layers can be left installed at a negligible cost.
+//!
 //! [`Service`]: tower_service::Service
+//! [`Span`]: tracing::Span
+//! [`HttpLayerBuilder::propagator`]: http::HttpLayerBuilder::propagator
+//! [`TextMapPropagator`]: opentelemetry::propagation::TextMapPropagator
+//! [`TextMapCompositePropagator`]: https://docs.rs/opentelemetry_sdk/latest/opentelemetry_sdk/propagation/struct.TextMapCompositePropagator.html
+//! [`set_text_map_propagator`]: opentelemetry::global::set_text_map_propagator
 
 #[doc(inline)]
 pub use self::{
-    grpc::{Grpc, GrpcLayer},
-    http::{Http, HttpLayer},
+    connection::ConnectionLayer,
+    grpc::{Grpc, GrpcLayer, GrpcTraceConfig},
+    headers::{HeaderFormat, HeaderPolicy, NonUtf8HeaderValue},
+    http::{Http, HttpLayer, HttpTraceConfig},
+    injector::InjectMode,
+    proxy::{Proxy, ProxyLayer},
 };
 
+pub mod connection;
+pub mod extensions;
 mod extractor;
 pub mod grpc;
+mod headers;
 pub mod http;
 mod injector;
+pub mod proxy;
+mod record;
+
+/// Describes the relationship between the [`Span`] and the service producing the span.
+///
+/// [`Span`]: tracing::Span
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpanKind {
+    /// The span describes a request sent to some remote service.
+    Client,
+    /// The span describes the server-side handling of a request.
+    #[default]
+    Server,
+}
@@ -0,0 +1,56 @@
+//! Middleware that adds tracing to a proxy, e.g. an API gateway.
+//!
+//! The [`ProxyLayer`] combines two [`HttpLayer`]s: the inbound request is traced by a server
+//! [`Span`], then the request relayed by the inner service is traced by a client [`Span`], child
+//! of the server one. The context of the client [`Span`] is injected in the headers of the
+//! relayed request, so that the upstream service continues the same trace.
+//!
+//! [`Span`]: tracing::Span
+
+use tower_layer::Layer;
+use tracing::Level;
+
+use super::{HeaderPolicy, Http, HttpLayer};
+
+/// Middleware that adds tracing to a proxy, see the [module documentation](self).
+pub type Proxy<S> = Http<Http<S>>;
+
+/// [`Layer`] that adds tracing to a proxy, see the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct ProxyLayer {
+    server: HttpLayer,
+    client: HttpLayer,
+}
+
+impl ProxyLayer {
+    /// [`Span`]s are constructed at the given level.
+    ///
+    /// The headers are recorded only by the server [`Span`], since the relayed request carries
+    /// the same headers.
+    ///
+    /// [`Span`]: tracing::Span
+    pub fn new(level: Level) -> Self {
+        Self {
+            server: HttpLayer::server(level),
+            client: HttpLayer::builder()
+                .client()
+                .level(level)
+                .header_policy(HeaderPolicy::Off)
+                .build(),
+        }
+    }
+
+    /// The inbound and the relayed requests are traced by the given layers, they should be
+    /// respectively a server and a client [`HttpLayer`].
+    pub fn from_layers(server: HttpLayer, client: HttpLayer) -> Self {
+        Self { server, client }
+    }
+}
+
+impl<S> Layer<S> for ProxyLayer {
+    type Service = Proxy<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        self.server.layer(self.client.layer(inner))
+    }
+}
@@ -0,0 +1,72 @@
+//! Recording of the fields shared by the HTTP and the gRPC spans.
+
+use std::fmt::Display;
+
+use http::{uri::Authority, Request};
+use tracing::{Level, Span};
+
+use crate::util::error_type;
+
+use super::{extensions::TlsInfo, grpc};
+
+/// Authority of the request, read from the URI or from the `Host` header.
+pub(crate) fn request_authority<B>(request: &Request<B>) -> Option<Authority> {
+    request.uri().authority().cloned().or_else(|| {
+        request
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|header_value| header_value.to_str().ok())
+            .and_then(|host| host.parse::<Authority>().ok())
+    })
+}
+
+/// Records the parameters negotiated by the TLS connection.
+pub(crate) fn record_tls_info(span: &Span, tls_info: &TlsInfo) {
+    if let Some(protocol_version) = &tls_info.protocol_version {
+        span.record("tls.protocol.version", protocol_version.as_str());
+    }
+    if let Some(cipher) = &tls_info.cipher {
+        span.record("tls.cipher", cipher.as_str());
+    }
+}
+
+/// Records the error message and the error type, i.e. the name of the type of the error (the
+/// errors of the `tower` middlewares are recorded as `timeout` and `overloaded`, the boxed ones
+/// as `_OTHER`).
+///
+/// When `event_target` is given, an event is also emitted at the [`Level::ERROR`] with it as
+/// target, it must be the target of the [`Span`] since the targets of the events are static.
+pub(crate) fn record_error<E: Display + 'static>(
+    span: &Span,
+    err: &E,
+    event_target: Option<&'static str>,
+) {
+    let message = err.to_string();
+    let error_type = error_type(err);
+    span.record("otel.status_code", "ERROR");
+    span.record("otel.status_message", message.as_str());
+    span.record("error.message", message.as_str());
+    span.record("error.type", error_type);
+
+    let Some(target) = event_target else {
+        return;
+    };
+    macro_rules! emit_event {
+        ($target:path) => {
+            tracing::event!(
+                target: $target,
+                Level::ERROR,
+                error.message = message.as_str(),
+                error.type = error_type,
+                "request failed"
+            )
+        };
+    }
+    match target {
+        super::http::CLIENT_TARGET => emit_event!(super::http::CLIENT_TARGET),
+        super::http::SERVER_TARGET => emit_event!(super::http::SERVER_TARGET),
+        grpc::CLIENT_TARGET => emit_event!(grpc::CLIENT_TARGET),
+        grpc::SERVER_TARGET => emit_event!(grpc::SERVER_TARGET),
+        _ => unreachable!("unknown target: {}", target),
+    }
+}
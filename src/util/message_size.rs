@@ -0,0 +1,81 @@
+//! Size of the messages transferred in a gRPC stream.
+
+use bytes::Buf;
+
+/// Length of the prefix of each gRPC message (compression flag and message length).
+const PREFIX_LEN: usize = 5;
+
+/// Parses the gRPC framing, summing the length of the messages without their prefix.
+///
+/// The length is the one sent on the wire, i.e. the messages are not decompressed.
+#[derive(Clone, Debug)]
+pub(crate) struct MessageSize {
+    prefix: [u8; PREFIX_LEN],
+    prefix_len: usize,
+    remaining: u64,
+    total: u64,
+    valid: bool,
+}
+
+impl MessageSize {
+    /// Creates an empty counter.
+    pub fn new() -> Self {
+        Self {
+            prefix: [0; PREFIX_LEN],
+            prefix_len: 0,
+            remaining: 0,
+            total: 0,
+            valid: true,
+        }
+    }
+
+    /// Updates the counter with the given data frame.
+    ///
+    /// Only the first chunk of the buffer can be inspected without consuming it, the following
+    /// bytes are counted only if they belong to the current message, otherwise the framing is
+    /// lost and the counter becomes invalid.
+    pub fn update<B: Buf>(&mut self, data: &B) {
+        if !self.valid {
+            return;
+        }
+
+        let chunk = data.chunk();
+        self.update_chunk(chunk);
+
+        let uninspected = (data.remaining() - chunk.len()) as u64;
+        if uninspected <= self.remaining {
+            self.remaining -= uninspected;
+            self.total += uninspected;
+        } else {
+            self.valid = false;
+        }
+    }
+
+    /// Returns the total size of the messages, if the framing has been parsed correctly.
+    pub fn total(&self) -> Option<u64> {
+        self.valid.then_some(self.total)
+    }
+
+    /// Updates the counter with the given bytes.
+    fn update_chunk(&mut self, mut chunk: &[u8]) {
+        while !chunk.is_empty() {
+            if self.remaining > 0 {
+                let len = chunk.len().min(self.remaining as usize);
+                self.remaining -= len as u64;
+                self.total += len as u64;
+                chunk = &chunk[len..];
+            } else {
+                let len = chunk.len().min(PREFIX_LEN - self.prefix_len);
+                self.prefix[self.prefix_len..self.prefix_len + len].copy_from_slice(&chunk[..len]);
+                self.prefix_len += len;
+                chunk = &chunk[len..];
+
+                if self.prefix_len == PREFIX_LEN {
+                    let [_, length @ ..] = self.prefix;
+                    self.remaining = u32::from_be_bytes(length) as u64;
+                    self.prefix_len = 0;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Utilities shared by the middlewares.
+
+use std::net::SocketAddr;
+
+use http::{
+    header::{CONTENT_LENGTH, FORWARDED, TRANSFER_ENCODING},
+    uri::Authority,
+    HeaderMap, Method, Request, Response, StatusCode, Version,
+};
+use http_body::Body;
+
+mod message_size;
+mod web_trailers;
+
+pub(crate) use self::{message_size::MessageSize, web_trailers::WebTrailers};
+
+/// Size of the request body, if exactly known
+pub(crate) fn http_request_size<B: Body>(request: &Request<B>) -> Option<u64> {
+    body_size(request.headers(), request.body())
+}
+
+/// Size of the response body, if exactly known
+///
+/// The responses to `HEAD` requests and the `1xx`, `204` and `304` responses have no body, their
+/// `Content-Length` header (if any) describes the representation and it is ignored.
+pub(crate) fn http_response_size<B: Body>(method: &Method, response: &Response<B>) -> Option<u64> {
+    let status = response.status();
+    let bodiless = *method == Method::HEAD
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED;
+    if bodiless {
+        return response.body().size_hint().exact();
+    }
+    body_size(response.headers(), response.body())
+}
+
+/// Size of the body, if exactly known
+///
+/// The `Content-Length` header is used first, then the size hint of the body. When the
+/// `Transfer-Encoding` is `chunked`, the `Content-Length` header must be ignored.
+fn body_size<B: Body>(headers: &HeaderMap, body: &B) -> Option<u64> {
+    let chunked = headers
+        .get_all(TRANSFER_ENCODING)
+        .iter()
+        .filter_map(|header_value| header_value.to_str().ok())
+        .flat_map(|transfer_encoding| transfer_encoding.split(','))
+        .any(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+
+    let content_length = if chunked {
+        None
+    } else {
+        headers
+            .get(CONTENT_LENGTH)
+            .and_then(|header_value| header_value.to_str().ok())
+            .and_then(|content_length| content_length.parse().ok())
+    };
+
+    content_length.or_else(|| body.size_hint().exact())
+}
+
+/// String representation of HTTP method
+///
+/// The methods that are not known are represented as `_OTHER`, the match is case-sensitive so a
+/// lowercase method (e.g. `get`) is not known.
+pub(crate) fn http_method(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::PATCH => "PATCH",
+        Method::TRACE => "TRACE",
+        _ if method.as_str() == "QUERY" => "QUERY",
+        _ => "_OTHER",
+    }
+}
+
+/// Address and port of the client, if known
+///
+/// From a request received behind a proxy, the first node of the `Forwarded` header is used
+/// first, then the first entry of the `X-Forwarded-For` header, otherwise the peer address of the
+/// connection is used (when the `axum` feature is enabled, from the `ConnectInfo` extension).
+pub(crate) fn client_address<B>(request: &Request<B>) -> Option<(String, Option<u16>)> {
+    let headers = request.headers();
+    let forwarded_for = headers
+        .get(FORWARDED)
+        .and_then(|header_value| header_value.to_str().ok())
+        .and_then(|forwarded| {
+            let forwarded = forwarded.split(',').next()?;
+            forwarded.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for").then_some(value)
+            })
+        });
+    let x_forwarded_for = || {
+        headers
+            .get("x-forwarded-for")
+            .and_then(|header_value| header_value.to_str().ok())
+            .and_then(|x_forwarded_for| x_forwarded_for.split(',').next())
+    };
+
+    forwarded_for
+        .or_else(x_forwarded_for)
+        .map(str::trim)
+        .map(|node| node.trim_matches('"'))
+        .filter(|node| !node.is_empty() && !node.eq_ignore_ascii_case("unknown"))
+        .map(|node| match node.parse::<SocketAddr>() {
+            Ok(socket_addr) => (socket_addr.ip().to_string(), Some(socket_addr.port())),
+            Err(_) => (node.trim_matches(&['[', ']'][..]).to_owned(), None),
+        })
+        .or_else(|| peer_address(request))
+}
+
+/// Address and port of the peer of the connection
+#[cfg(feature = "axum")]
+fn peer_address<B>(request: &Request<B>) -> Option<(String, Option<u16>)> {
+    request
+        .extensions()
+        .get::<axum::extract::ConnectInfo<SocketAddr>>()
+        .map(|connect_info| (connect_info.ip().to_string(), Some(connect_info.port())))
+}
+
+/// Address and port of the peer of the connection
+#[cfg(not(feature = "axum"))]
+fn peer_address<B>(_request: &Request<B>) -> Option<(String, Option<u16>)> {
+    None
+}
+
+/// Canonical type of the error, the name of the Rust type is used as fallback
+///
+/// The errors of the `tower` middlewares are recognized even if boxed, e.g. by `Buffer`, or
+/// wrapped as source of another error.
+#[cfg(feature = "tower")]
+pub(crate) fn error_type<E: 'static>(err: &E) -> &'static str {
+    use std::{any::Any, error::Error};
+
+    use tower::{load_shed::error::Overloaded, timeout::error::Elapsed, BoxError};
+
+    let any = err as &dyn Any;
+    if any.is::<Elapsed>() {
+        return "timeout";
+    }
+    if any.is::<Overloaded>() {
+        return "overloaded";
+    }
+
+    let mut source: Option<&(dyn Error + 'static)> =
+        any.downcast_ref::<BoxError>().map(|err| err.as_ref() as _);
+    while let Some(err) = source {
+        if err.is::<Elapsed>() {
+            return "timeout";
+        }
+        if err.is::<Overloaded>() {
+            return "overloaded";
+        }
+        source = err.source();
+    }
+    type_name::<E>()
+}
+
+/// Canonical type of the error, the name of the Rust type is used as fallback
+#[cfg(not(feature = "tower"))]
+pub(crate) fn error_type<E: 'static>(_err: &E) -> &'static str {
+    type_name::<E>()
+}
+
+/// Name of the Rust type of the error, the boxed errors (e.g. `BoxError`) are recorded as
+/// `_OTHER` since the name of the box does not describe the error
+fn type_name<E: 'static>() -> &'static str {
+    let type_name = std::any::type_name::<E>();
+    if type_name.starts_with("alloc::boxed::Box<") {
+        "_OTHER"
+    } else {
+        type_name
+    }
+}
+
+/// Host of the authority, the brackets of an IPv6 address are removed
+pub(crate) fn authority_host(authority: &Authority) -> &str {
+    let host = authority.host();
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Port of the authority, if not explicitly specified the default port of the scheme is used
+pub(crate) fn authority_port(authority: &Authority, scheme: Option<&str>) -> Option<u16> {
+    authority.port_u16().or(match scheme {
+        Some("http") => Some(80),
+        Some("https") => Some(443),
+        _ => None,
+    })
+}
+
+/// String representation of network protocol version
+pub(crate) fn http_version(version: Version) -> Option<&'static str> {
+    match version {
+        Version::HTTP_09 => Some("0.9"),
+        Version::HTTP_10 => Some("1.0"),
+        Version::HTTP_11 => Some("1.1"),
+        Version::HTTP_2 => Some("2"),
+        Version::HTTP_3 => Some("3"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use bytes::Bytes;
+    use http::{HeaderMap, HeaderValue, Method, Response, StatusCode};
+    use http_body::{Body, Frame};
+
+    use super::{body_size, error_type, http_method, http_response_size};
+
+    /// Body whose size is not known in advance.
+    struct Streaming;
+
+    impl Body for Streaming {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            Poll::Ready(None)
+        }
+    }
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|&(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn body_size_uses_content_length() {
+        let headers = headers(&[("content-length", "10")]);
+        assert_eq!(body_size(&headers, &Streaming), Some(10));
+    }
+
+    #[test]
+    fn body_size_ignores_content_length_of_chunked_body() {
+        let headers = headers(&[
+            ("transfer-encoding", "gzip, chunked"),
+            ("content-length", "10"),
+        ]);
+        assert_eq!(body_size(&headers, &Streaming), None);
+        assert_eq!(body_size(&headers, &String::from("hello")), Some(5));
+    }
+
+    #[test]
+    fn body_size_without_headers() {
+        assert_eq!(body_size(&HeaderMap::new(), &Streaming), None);
+        assert_eq!(
+            body_size(&HeaderMap::new(), &String::from("hello")),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn http_response_size_ignores_content_length_without_body() {
+        let cases = [
+            (Method::GET, StatusCode::OK, Some(10)),
+            (Method::HEAD, StatusCode::OK, Some(0)),
+            (Method::GET, StatusCode::NO_CONTENT, Some(0)),
+            (Method::GET, StatusCode::NOT_MODIFIED, Some(0)),
+        ];
+        for (method, status, expected) in cases {
+            let response = Response::builder()
+                .status(status)
+                .header("content-length", "10")
+                .body(String::new())
+                .unwrap();
+            assert_eq!(http_response_size(&method, &response), expected);
+        }
+    }
+
+    #[test]
+    fn error_type_of_boxed_error() {
+        let err: Box<dyn std::error::Error + Send + Sync> = "connection reset".into();
+        assert_eq!(error_type(&err), "_OTHER");
+        assert_eq!(error_type(&std::fmt::Error), "core::fmt::Error");
+    }
+
+    #[test]
+    fn http_method_names() {
+        let cases = [
+            ("GET", "GET"),
+            ("POST", "POST"),
+            ("PUT", "PUT"),
+            ("DELETE", "DELETE"),
+            ("HEAD", "HEAD"),
+            ("OPTIONS", "OPTIONS"),
+            ("CONNECT", "CONNECT"),
+            ("PATCH", "PATCH"),
+            ("TRACE", "TRACE"),
+            ("QUERY", "QUERY"),
+            ("PURGE", "_OTHER"),
+            ("get", "_OTHER"),
+        ];
+        for (method, expected) in cases {
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            assert_eq!(http_method(&method), expected, "method {}", method);
+        }
+    }
+}
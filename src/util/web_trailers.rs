@@ -0,0 +1,136 @@
+//! Trailers of a gRPC-Web response, encoded in the body.
+
+use bytes::Buf;
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+/// Length of the prefix of each gRPC-Web frame (flags and frame length).
+const PREFIX_LEN: usize = 5;
+
+/// Flag of the frame carrying the trailers.
+const TRAILERS_FLAG: u8 = 0x80;
+
+/// Parses the gRPC-Web framing, collecting the trailers sent in the last frame.
+///
+/// The trailers are encoded as HTTP/1 headers, e.g. `grpc-status: 0\r\n`, the text format of
+/// gRPC-Web (base64 encoded) is not supported.
+#[derive(Clone, Debug)]
+pub(crate) struct WebTrailers {
+    prefix: [u8; PREFIX_LEN],
+    prefix_len: usize,
+    remaining: u64,
+    trailers: Option<Vec<u8>>,
+    valid: bool,
+}
+
+impl WebTrailers {
+    /// Creates an empty parser.
+    pub fn new() -> Self {
+        Self {
+            prefix: [0; PREFIX_LEN],
+            prefix_len: 0,
+            remaining: 0,
+            trailers: None,
+            valid: true,
+        }
+    }
+
+    /// Updates the parser with the given data frame, returns the trailers once the frame carrying
+    /// them is complete.
+    ///
+    /// Only the first chunk of the buffer can be inspected without consuming it, if the buffer is
+    /// not contiguous the framing is lost and the parser becomes invalid.
+    pub fn update<B: Buf>(&mut self, data: &B) -> Option<HeaderMap> {
+        if !self.valid {
+            return None;
+        }
+
+        let chunk = data.chunk();
+        if chunk.len() != data.remaining() {
+            self.valid = false;
+            return None;
+        }
+        self.update_chunk(chunk)
+    }
+
+    /// Updates the parser with the given bytes.
+    fn update_chunk(&mut self, mut chunk: &[u8]) -> Option<HeaderMap> {
+        while !chunk.is_empty() {
+            if self.remaining > 0 {
+                let len = chunk.len().min(self.remaining as usize);
+                if let Some(trailers) = &mut self.trailers {
+                    trailers.extend_from_slice(&chunk[..len]);
+                }
+                self.remaining -= len as u64;
+                chunk = &chunk[len..];
+            } else {
+                let len = chunk.len().min(PREFIX_LEN - self.prefix_len);
+                self.prefix[self.prefix_len..self.prefix_len + len].copy_from_slice(&chunk[..len]);
+                self.prefix_len += len;
+                chunk = &chunk[len..];
+
+                if self.prefix_len == PREFIX_LEN {
+                    let [flags, length @ ..] = self.prefix;
+                    self.remaining = u32::from_be_bytes(length) as u64;
+                    self.prefix_len = 0;
+                    if flags & TRAILERS_FLAG != 0 {
+                        self.trailers = Some(Vec::new());
+                    }
+                }
+            }
+
+            if self.remaining == 0 && self.prefix_len == 0 {
+                if let Some(trailers) = self.trailers.take() {
+                    return Some(parse_trailers(&trailers));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses the trailers encoded as HTTP/1 headers, the malformed lines are skipped.
+fn parse_trailers(trailers: &[u8]) -> HeaderMap {
+    trailers
+        .split(|&byte| byte == b'\n')
+        .filter_map(|line| {
+            let colon = line.iter().position(|&byte| byte == b':')?;
+            let name = HeaderName::from_bytes(line[..colon].trim_ascii()).ok()?;
+            let value = HeaderValue::from_bytes(line[colon + 1..].trim_ascii()).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::WebTrailers;
+
+    #[test]
+    fn trailers_after_message() {
+        let mut web_trailers = WebTrailers::new();
+        let trailers = b"grpc-status: 5\r\nGrpc-Message: not found\r\n";
+        let mut frame = vec![0, 0, 0, 0, 2, 1, 2, 0x80, 0, 0, 0, trailers.len() as u8];
+        frame.extend_from_slice(trailers);
+
+        // the trailers frame is split across the data frames
+        let (first, second) = frame.split_at(10);
+        assert!(web_trailers
+            .update(&Bytes::copy_from_slice(first))
+            .is_none());
+        let trailers = web_trailers
+            .update(&Bytes::copy_from_slice(second))
+            .expect("trailers not found");
+        assert_eq!(trailers["grpc-status"], "5");
+        assert_eq!(trailers["grpc-message"], "not found");
+    }
+
+    #[test]
+    fn no_trailers() {
+        let mut web_trailers = WebTrailers::new();
+        assert!(web_trailers
+            .update(&Bytes::from_static(&[0, 0, 0, 0, 1, 1]))
+            .is_none());
+    }
+}
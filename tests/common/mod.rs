@@ -0,0 +1,289 @@
+//! Test harness that collects the spans emitted by the middlewares in memory.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    future::{ready, Future, Ready},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_executor::block_on;
+use http::HeaderMap;
+use http_body::{Body, Frame};
+use opentelemetry::{
+    propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
+    trace::{
+        SpanContext, SpanId, SpanKind, TraceContextExt, TraceFlags, TraceId, TraceState,
+        TracerProvider as _,
+    },
+    Array, Context as OtelContext, Value,
+};
+use opentelemetry_sdk::{
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    trace::TracerProvider,
+};
+use tower_service::Service;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    layer::SubscriberExt,
+    Layer as _,
+};
+
+/// Exporter that stores the finished spans in memory.
+#[derive(Clone, Debug, Default)]
+struct InMemoryExporter {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl SpanExporter for InMemoryExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send>> {
+        self.spans.lock().unwrap().extend(batch);
+        Box::pin(ready(Ok(())))
+    }
+}
+
+/// Runs the given closure with a subscriber that exports the spans in memory, the finished spans
+/// are returned in the order in which they are closed.
+pub fn collect_spans<F>(f: F) -> Vec<SpanData>
+where
+    F: FnOnce(),
+{
+    collect_filtered_spans(Targets::new().with_default(LevelFilter::TRACE), f)
+}
+
+/// Same as [`collect_spans`], only the spans enabled by the given targets are exported.
+pub fn collect_filtered_spans<F>(targets: Targets, f: F) -> Vec<SpanData>
+where
+    F: FnOnce(),
+{
+    let exporter = InMemoryExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let tracer = provider.tracer("tower-otel");
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(targets),
+    );
+
+    tracing::subscriber::with_default(subscriber, f);
+    provider.force_flush();
+
+    let spans = exporter.spans.lock().unwrap().clone();
+    spans
+}
+
+/// Calls the service with the given request, blocking until the response is received.
+pub fn call<S, R>(mut service: S, request: R) -> Result<S::Response, S::Error>
+where
+    S: Service<R>,
+{
+    block_on(std::future::poll_fn(|cx| service.poll_ready(cx)))?;
+    block_on(service.call(request))
+}
+
+/// Returns the value of the given attribute of the span.
+pub fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == key)
+        .map(|attribute| attribute.value.clone())
+}
+
+/// Returns the array of strings recorded for a header or a metadata.
+pub fn strings(values: &[&str]) -> Value {
+    Value::Array(Array::String(
+        values
+            .iter()
+            .map(|value| value.to_string().into())
+            .collect(),
+    ))
+}
+
+/// Returns the span with the given kind, panicking if there is not exactly one.
+pub fn find_span(spans: &[SpanData], kind: SpanKind) -> &SpanData {
+    let mut spans = spans.iter().filter(|span| span.span_kind == kind);
+    let span = spans.next().expect("span not found");
+    assert!(spans.next().is_none(), "more than one span found");
+    span
+}
+
+/// Service that responds to each request using the given function.
+#[derive(Clone)]
+pub struct ServiceFn<F>(pub F);
+
+impl<F, Req, Res> Service<Req> for ServiceFn<F>
+where
+    F: FnMut(Req) -> Res,
+{
+    type Response = Res;
+    type Error = Infallible;
+    type Future = Ready<Result<Res, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        ready(Ok((self.0)(request)))
+    }
+}
+
+/// Body that yields the given data frames, followed by the trailers.
+pub struct FramesBody {
+    frames: VecDeque<Frame<Bytes>>,
+}
+
+impl FramesBody {
+    /// Creates a body with the given data frames and trailers.
+    pub fn new(data: &[&[u8]], trailers: HeaderMap) -> Self {
+        let frames = data
+            .iter()
+            .map(|data| Frame::data(Bytes::copy_from_slice(data)))
+            .chain([Frame::trailers(trailers)])
+            .collect();
+        Self { frames }
+    }
+}
+
+impl Body for FramesBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Poll::Ready(self.frames.pop_front().map(Ok))
+    }
+}
+
+/// Polls all the frames of the body, blocking until the end of the stream.
+pub fn consume<B>(body: B)
+where
+    B: Body,
+{
+    let mut body = std::pin::pin!(body);
+    while block_on(std::future::poll_fn(|cx| body.as_mut().poll_frame(cx))).is_some() {}
+}
+
+/// Error returned by the [`Failing`] service.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Service that fails each request with the given message.
+#[derive(Clone)]
+pub struct Failing(pub &'static str);
+
+impl<Req> Service<Req> for Failing {
+    type Response = http::Response<String>;
+    type Error = BoxError;
+    type Future = Ready<Result<Self::Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: Req) -> Self::Future {
+        ready(Err(self.0.into()))
+    }
+}
+
+/// Service that fails each request with the error returned by the given function.
+#[derive(Clone)]
+pub struct FailingWith<F>(pub F);
+
+impl<Req, F> Service<Req> for FailingWith<F>
+where
+    F: Fn() -> BoxError,
+{
+    type Response = http::Response<String>;
+    type Error = BoxError;
+    type Future = Ready<Result<Self::Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: Req) -> Self::Future {
+        ready(Err((self.0)()))
+    }
+}
+
+/// Service whose responses never complete.
+#[derive(Clone)]
+pub struct Pending;
+
+impl<Req> Service<Req> for Pending {
+    type Response = http::Response<String>;
+    type Error = Infallible;
+    type Future = std::future::Pending<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _request: Req) -> Self::Future {
+        std::future::pending()
+    }
+}
+
+/// Minimal propagator of the multi-header `b3` format (`x-b3-traceid`, `x-b3-spanid` and
+/// `x-b3-sampled`), the debug flag and the parent span id are not supported.
+#[derive(Clone, Debug)]
+pub struct B3Propagator {
+    fields: [String; 3],
+}
+
+impl B3Propagator {
+    pub fn new() -> Self {
+        Self {
+            fields: ["x-b3-traceid", "x-b3-spanid", "x-b3-sampled"].map(String::from),
+        }
+    }
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, cx: &OtelContext, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+        let sampled = if span_context.is_sampled() { "1" } else { "0" };
+        injector.set("x-b3-traceid", span_context.trace_id().to_string());
+        injector.set("x-b3-spanid", span_context.span_id().to_string());
+        injector.set("x-b3-sampled", sampled.to_owned());
+    }
+
+    fn extract_with_context(&self, cx: &OtelContext, extractor: &dyn Extractor) -> OtelContext {
+        let trace_id = extractor
+            .get("x-b3-traceid")
+            .and_then(|trace_id| TraceId::from_hex(trace_id).ok());
+        let span_id = extractor
+            .get("x-b3-spanid")
+            .and_then(|span_id| SpanId::from_hex(span_id).ok());
+        let (Some(trace_id), Some(span_id)) = (trace_id, span_id) else {
+            return cx.clone();
+        };
+        let trace_flags = match extractor.get("x-b3-sampled") {
+            Some("1") => TraceFlags::SAMPLED,
+            _ => TraceFlags::default(),
+        };
+        let span_context =
+            SpanContext::new(trace_id, span_id, trace_flags, true, TraceState::default());
+        cx.with_remote_span_context(span_context)
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
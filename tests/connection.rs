@@ -0,0 +1,54 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use http::{Request, Response};
+use opentelemetry::trace::SpanKind;
+use tower_layer::Layer;
+use tower_otel::trace::{ConnectionLayer, HttpLayer};
+use tracing::Level;
+
+use common::{attribute, call, collect_spans, ServiceFn};
+
+#[test]
+fn connection_span_records_peer_and_links_requests() {
+    let spans = collect_spans(|| {
+        let make_service = ConnectionLayer::new(Level::INFO).layer(ServiceFn(|_: SocketAddr| {
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|_| Response::new(String::new())))
+        }));
+        let peer: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let connection = call(make_service, peer).unwrap();
+        for _ in 0..2 {
+            let request = Request::get("/").body(String::new()).unwrap();
+            call(connection.clone(), request).unwrap();
+        }
+    });
+
+    let connection = spans
+        .iter()
+        .find(|span| span.name == "CONNECTION")
+        .expect("connection span not found");
+    assert_eq!(connection.span_kind, SpanKind::Internal);
+    assert_eq!(
+        attribute(connection, "network.peer.address"),
+        Some("192.0.2.1".into())
+    );
+    assert_eq!(
+        attribute(connection, "network.peer.port"),
+        Some(50000.into())
+    );
+    assert_eq!(
+        attribute(connection, "network.transport"),
+        Some("tcp".into())
+    );
+
+    let requests: Vec<_> = spans.iter().filter(|span| span.name == "GET").collect();
+    assert_eq!(requests.len(), 2);
+    for request in requests {
+        let linked = request
+            .links
+            .iter()
+            .any(|link| link.span_context == connection.span_context);
+        assert!(linked, "request span not linked to the connection span");
+    }
+}
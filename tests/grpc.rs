@@ -0,0 +1,643 @@
+mod common;
+
+use http::{Request, Response};
+use opentelemetry::trace::{SpanKind, Status};
+use tower_layer::Layer;
+use tower_otel::trace::GrpcLayer;
+use tracing::Level;
+
+use common::{attribute, call, collect_spans, find_span, ServiceFn};
+
+fn grpc_response(status: &str) -> Response<String> {
+    Response::builder()
+        .header("content-type", "application/grpc")
+        .header("grpc-status", status)
+        .body(String::new())
+        .unwrap()
+}
+
+#[test]
+fn server_span_records_rpc_attributes() {
+    let spans = collect_spans(|| {
+        // the inner service receives the request body unchanged
+        let service = GrpcLayer::server(Level::INFO)
+            .layer(ServiceFn(|_: Request<String>| grpc_response("0")));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .version(http::Version::HTTP_2)
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(span.name, "helloworld.Greeter/SayHello");
+    assert_eq!(attribute(span, "rpc.system"), Some("grpc".into()));
+    assert_eq!(
+        attribute(span, "network.protocol.name"),
+        Some("http".into())
+    );
+    assert_eq!(
+        attribute(span, "network.protocol.version"),
+        Some("2".into())
+    );
+    assert_eq!(
+        attribute(span, "rpc.service"),
+        Some("helloworld.Greeter".into())
+    );
+    assert_eq!(attribute(span, "rpc.method"), Some("SayHello".into()));
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(0.into()));
+    assert_eq!(
+        attribute(span, "rpc.message.encoding"),
+        Some("proto".into())
+    );
+    assert_eq!(span.status, Status::Unset);
+}
+
+#[test]
+fn server_span_is_error_on_internal() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(ServiceFn(|_| grpc_response("13")));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(13.into()));
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn server_span_is_error_on_custom_status_code() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .status_error(|status_code| status_code == 5)
+            .build()
+            .layer(ServiceFn(|_| grpc_response("5")));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(5.into()));
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn client_span_is_error_on_not_found() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| grpc_response("5")));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(5.into()));
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn server_span_records_json_encoding() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(ServiceFn(|_| grpc_response("0")));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc+json")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "rpc.message.encoding"), Some("json".into()));
+}
+
+#[test]
+fn client_span_is_cancelled_when_dropped() {
+    use tower_service::Service;
+
+    use common::Pending;
+
+    let spans = collect_spans(|| {
+        let mut service = GrpcLayer::client(Level::INFO).layer(Pending);
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        drop(service.call(request));
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(1.into()));
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn server_span_is_cancelled_when_dropped() {
+    use tower_service::Service;
+
+    use common::Pending;
+
+    let spans = collect_spans(|| {
+        let mut service = GrpcLayer::server(Level::INFO).layer(Pending);
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        drop(service.call(request));
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(1.into()));
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn server_span_records_layer_attributes() {
+    use opentelemetry::KeyValue;
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .attributes(vec![KeyValue::new("service.namespace", "billing")])
+            .build()
+            .layer(ServiceFn(|_| grpc_response("0")));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "service.namespace"), Some("billing".into()));
+}
+
+#[test]
+fn filtered_span_does_not_insert_context_extension() {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+
+    use common::collect_filtered_spans;
+
+    let targets = Targets::new()
+        .with_default(LevelFilter::TRACE)
+        .with_target("tower_otel::trace::grpc::server", LevelFilter::OFF);
+    let spans = collect_filtered_spans(targets, || {
+        let service =
+            GrpcLayer::server(Level::INFO).layer(ServiceFn(|request: Request<String>| {
+                assert!(request
+                    .extensions()
+                    .get::<opentelemetry::Context>()
+                    .is_none());
+                grpc_response("0")
+            }));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    assert!(spans.is_empty());
+}
+
+#[test]
+fn filtered_rpc_is_not_traced() {
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::builder()
+            .client()
+            .level(Level::INFO)
+            .propagator(TraceContextPropagator::new())
+            .filter(|service, method| !(service == "grpc.health.v1.Health" && method == "Check"))
+            .build()
+            .layer(ServiceFn(|request: Request<String>| {
+                assert!(!request.headers().contains_key("traceparent"));
+                assert!(request
+                    .extensions()
+                    .get::<opentelemetry::Context>()
+                    .is_none());
+                grpc_response("0")
+            }));
+        let request = Request::post("http://example.com/grpc.health.v1.Health/Check")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        common::consume(response.into_body());
+    });
+
+    assert!(spans.is_empty());
+}
+
+#[test]
+fn layer_from_config() {
+    use tower_otel::trace::{GrpcTraceConfig, SpanKind as TraceKind};
+
+    let spans = collect_spans(|| {
+        let mut config = GrpcTraceConfig::default();
+        config.kind = TraceKind::Client;
+        config.rpc_system = Some("custom".to_owned());
+        let service = GrpcLayer::from_config(config).layer(ServiceFn(|_| grpc_response("0")));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.system"), Some("custom".into()));
+}
+
+#[test]
+fn client_span_records_message_size() {
+    use http::HeaderMap;
+
+    use common::{consume, FramesBody};
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::builder()
+            .client()
+            .level(Level::INFO)
+            .message_size(true)
+            .build()
+            .layer(ServiceFn(|_| {
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", "0".parse().unwrap());
+                // two messages of 3 and 2 bytes, the second one split across the frames
+                let body = FramesBody::new(
+                    &[&[0, 0, 0, 0, 3, 1, 2, 3, 0, 0], &[0, 0, 2, 4, 5]],
+                    trailers,
+                );
+                Response::builder()
+                    .header("content-type", "application/grpc")
+                    .body(body)
+                    .unwrap()
+            }));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.response.size"), Some(5.into()));
+    assert_eq!(attribute(span, "rpc.grpc.request.size"), None);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(0.into()));
+}
+
+#[test]
+fn server_span_records_request_message_size() {
+    use http::HeaderMap;
+
+    use tower_otel::trace::grpc::RequestSizeLayer;
+
+    use common::{consume, FramesBody};
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(RequestSizeLayer::new().layer(
+            ServiceFn(|request: Request<_>| {
+                consume(request.into_body());
+                grpc_response("0")
+            }),
+        ));
+        // two messages of 1 and 4 bytes, the prefix of the second one split across the frames
+        let body = FramesBody::new(
+            &[&[0, 0, 0, 0, 1, 1, 0, 0], &[0, 0, 4, 1, 2, 3, 4]],
+            HeaderMap::new(),
+        );
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(body)
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "rpc.grpc.request.size"), Some(5.into()));
+    assert_eq!(attribute(span, "rpc.grpc.response.size"), None);
+}
+
+#[test]
+fn server_span_skips_names_of_malformed_path() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(ServiceFn(|_| grpc_response("12")));
+        let request = Request::post("/helloworld.Greeter/SayHello/extra")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(span.name, "grpc");
+    assert_eq!(attribute(span, "rpc.service"), None);
+    assert_eq!(attribute(span, "rpc.method"), None);
+}
+
+#[test]
+fn server_span_records_forwarded_client() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(ServiceFn(|_| grpc_response("0")));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .header("x-forwarded-for", "203.0.113.195, 70.41.3.18")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "client.address"),
+        Some("203.0.113.195".into())
+    );
+    assert_eq!(attribute(span, "client.port"), None);
+}
+
+#[test]
+fn server_span_prefers_server_name_indication() {
+    use tower_otel::trace::extensions::TlsInfo;
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(ServiceFn(|_| grpc_response("0")));
+        let mut request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .header("host", "10.0.0.1:8443")
+            .body(String::new())
+            .unwrap();
+        request.extensions_mut().insert(TlsInfo {
+            server_name: Some("api.example.com".to_owned()),
+            ..Default::default()
+        });
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "server.address"),
+        Some("api.example.com".into())
+    );
+    assert_eq!(attribute(span, "server.port"), Some(8443.into()));
+}
+
+#[test]
+fn client_span_records_deadline_exceeded() {
+    use http::HeaderMap;
+
+    use common::{consume, FramesBody};
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", "4".parse().unwrap());
+            trailers.insert("grpc-message", "Timeout%20expired".parse().unwrap());
+            Response::builder()
+                .header("content-type", "application/grpc")
+                .body(FramesBody::new(&[], trailers))
+                .unwrap()
+        }));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(4.into()));
+    assert_eq!(
+        attribute(span, "error.message"),
+        Some("Timeout expired".into())
+    );
+    assert_eq!(
+        attribute(span, "error.type"),
+        Some("DEADLINE_EXCEEDED".into())
+    );
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn client_span_records_default_server_port() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| grpc_response("0")));
+        let request = Request::post("https://[::1]/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "server.address"), Some("::1".into()));
+    assert_eq!(attribute(span, "server.port"), Some(443.into()));
+}
+
+#[test]
+fn completed_client_future_does_not_record_cancellation() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| grpc_response("4")));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    let status_codes = span
+        .attributes
+        .iter()
+        .filter(|attribute| attribute.key.as_str() == "rpc.grpc.status_code")
+        .count();
+    assert_eq!(status_codes, 1);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(4.into()));
+    assert_eq!(
+        attribute(span, "error.type"),
+        Some("DEADLINE_EXCEEDED".into())
+    );
+}
+
+#[test]
+fn server_span_status_description_is_grpc_message() {
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(ServiceFn(|_| {
+            Response::builder()
+                .header("content-type", "application/grpc")
+                .header("grpc-status", "13")
+                .header("grpc-message", "database%20unavailable")
+                .body(String::new())
+                .unwrap()
+        }));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        span.status,
+        Status::Error {
+            description: "database unavailable".into()
+        }
+    );
+    assert_eq!(attribute(span, "otel.status_message"), None);
+}
+
+#[test]
+fn client_span_records_status_code_from_trailers() {
+    use http::HeaderMap;
+
+    use common::{consume, FramesBody};
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", "5".parse().unwrap());
+            Response::builder()
+                .header("content-type", "application/grpc")
+                .body(FramesBody::new(&[b"\0\0\0\0\0"], trailers))
+                .unwrap()
+        }));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(5.into()));
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn server_span_records_status_code_from_trailers() {
+    use http::HeaderMap;
+
+    use common::{consume, FramesBody};
+
+    let spans = collect_spans(|| {
+        // the handler fails with NOT_FOUND, sent in the trailers after the (empty) messages
+        let service = GrpcLayer::server(Level::INFO).layer(ServiceFn(|_| {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", "5".parse().unwrap());
+            trailers.insert("grpc-message", "user%20not%20found".parse().unwrap());
+            Response::builder()
+                .header("content-type", "application/grpc")
+                .body(FramesBody::new(&[], trailers))
+                .unwrap()
+        }));
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(5.into()));
+    // NOT_FOUND is a failure of the client, not of the server
+    assert_eq!(span.status, Status::Unset);
+    assert_eq!(attribute(span, "error.type"), None);
+}
+
+#[test]
+fn client_span_leaves_status_code_unset_without_status_code() {
+    use common::consume;
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| {
+            Response::builder()
+                .header("content-type", "application/grpc")
+                .body(String::new())
+                .unwrap()
+        }));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), None);
+    assert_eq!(span.status, Status::Unset);
+}
+
+#[test]
+fn client_span_records_status_code_from_grpc_web_body() {
+    use http::HeaderMap;
+
+    use common::{consume, FramesBody};
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| {
+            // a message of 1 byte, followed by the trailers frame
+            let body = FramesBody::new(
+                &[&[0, 0, 0, 0, 1, 1], b"\x80\0\0\0\x10grpc-status: 5\r\n"],
+                HeaderMap::new(),
+            );
+            Response::builder()
+                .header("content-type", "application/grpc-web+proto")
+                .body(body)
+                .unwrap()
+        }));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc-web+proto")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.web"), Some(true.into()));
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), Some(5.into()));
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn client_span_leaves_grpc_web_status_code_unset_without_trailers() {
+    use common::consume;
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::client(Level::INFO).layer(ServiceFn(|_| {
+            Response::builder()
+                .header("content-type", "application/grpc-web+proto")
+                .body(String::new())
+                .unwrap()
+        }));
+        let request = Request::post("http://example.com/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc-web+proto")
+            .body(String::new())
+            .unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "rpc.grpc.status_code"), None);
+    assert_eq!(span.status, Status::Unset);
+}
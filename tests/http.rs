@@ -0,0 +1,1403 @@
+mod common;
+
+use http::{Request, Response, StatusCode};
+use opentelemetry::trace::{SpanKind, Status};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tower_layer::Layer;
+use tower_otel::trace::HttpLayer;
+use tracing::Level;
+
+use common::{attribute, call, collect_spans, find_span, strings, ServiceFn};
+
+#[test]
+fn server_span_records_request_and_response() {
+    let spans = collect_spans(|| {
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/users?id=1").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(span.name, "GET");
+    assert_eq!(attribute(span, "http.request.method"), Some("GET".into()));
+    assert_eq!(
+        attribute(span, "http.response.status_code"),
+        Some(200.into())
+    );
+    assert_eq!(attribute(span, "url.path"), Some("/users".into()));
+    assert_eq!(attribute(span, "url.query"), Some("id=1".into()));
+    assert_eq!(span.status, Status::Unset);
+}
+
+#[test]
+fn server_span_is_error_on_server_error() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::server(Level::INFO).layer(ServiceFn(|_| {
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(String::new())
+                .unwrap()
+        }));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "http.response.status_code"),
+        Some(500.into())
+    );
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn client_span_is_error_on_client_error() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::client(Level::INFO).layer(ServiceFn(|_| {
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(String::new())
+                .unwrap()
+        }));
+        let request = Request::get("http://example.com:8080/")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(
+        attribute(span, "server.address"),
+        Some("example.com".into())
+    );
+    assert_eq!(attribute(span, "server.port"), Some(8080.into()));
+    assert_eq!(
+        attribute(span, "url.full"),
+        Some("http://example.com:8080/".into())
+    );
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn server_span_records_normalized_route() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .normalized_route()
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/users/42/posts/67e55044-10b1-426f-9247-bb680e5fe0c8")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(span.name, "GET /users/{id}/posts/{id}");
+    assert_eq!(
+        attribute(span, "http.route"),
+        Some("/users/{id}/posts/{id}".into())
+    );
+}
+
+#[test]
+fn client_span_is_child_of_context_extension() {
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+
+    let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+    let span_id = SpanId::from_hex("00f067aa0ba902b7").unwrap();
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .client()
+            .level(Level::INFO)
+            .propagator(TraceContextPropagator::new())
+            .build()
+            .layer(ServiceFn(|request: Request<String>| {
+                let traceparent = request.headers()["traceparent"].to_str().unwrap();
+                assert!(traceparent.contains("4bf92f3577b34da6a3ce929d0e0e4736"));
+                Response::new(String::new())
+            }));
+        let span_context = SpanContext::new(
+            trace_id,
+            span_id,
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let context = opentelemetry::Context::new().with_remote_span_context(span_context);
+        let mut request = Request::get("http://example.com/")
+            .body(String::new())
+            .unwrap();
+        request.extensions_mut().insert(context);
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(span.span_context.trace_id(), trace_id);
+    assert_eq!(span.parent_span_id, span_id);
+}
+
+#[test]
+fn server_span_records_non_utf8_header_placeholder() {
+    use http::HeaderValue;
+    use tower_otel::trace::NonUtf8HeaderValue;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .non_utf8_header_value(NonUtf8HeaderValue::Placeholder)
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/")
+            .header("x-binary", HeaderValue::from_bytes(b"\xff\xfe").unwrap())
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "http.request.header.x-binary"),
+        Some(strings(&["<binary len=2>"]))
+    );
+}
+
+#[test]
+fn client_span_uses_default_scheme_for_origin_form() {
+    use http::uri::Scheme;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .client()
+            .level(Level::INFO)
+            .default_scheme(Scheme::HTTPS)
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/users?id=1")
+            .header("host", "example.com")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "url.scheme"), Some("https".into()));
+    assert_eq!(
+        attribute(span, "server.address"),
+        Some("example.com".into())
+    );
+    assert_eq!(attribute(span, "server.port"), Some(443.into()));
+    assert_eq!(
+        attribute(span, "url.full"),
+        Some("https://example.com/users?id=1".into())
+    );
+}
+
+#[test]
+fn layers_compose_with_boxed_errors() {
+    use tower_otel::trace::GrpcLayer;
+
+    use common::Failing;
+
+    let spans = collect_spans(|| {
+        let service = GrpcLayer::server(Level::INFO).layer(Failing("connection reset"));
+        let service = HttpLayer::server(Level::INFO).layer(service);
+        let request = Request::post("/helloworld.Greeter/SayHello")
+            .header("content-type", "application/grpc")
+            .body(String::new())
+            .unwrap();
+        let err = call(service, request).err().unwrap();
+        assert_eq!(err.to_string(), "connection reset");
+    });
+
+    assert_eq!(spans.len(), 2);
+    for span in &spans {
+        assert_eq!(
+            attribute(span, "error.message"),
+            Some("connection reset".into())
+        );
+        assert!(matches!(span.status, Status::Error { .. }));
+    }
+}
+
+#[test]
+fn server_span_records_content_type_regardless_of_policy() {
+    use tower_otel::trace::{HeaderFormat, HeaderPolicy};
+
+    let cases = [
+        (HeaderPolicy::Off, HeaderFormat::Attributes, None),
+        (
+            HeaderPolicy::All,
+            HeaderFormat::Attributes,
+            Some(strings(&["42"])),
+        ),
+        (HeaderPolicy::Off, HeaderFormat::Json, None),
+        (HeaderPolicy::All, HeaderFormat::Json, None),
+    ];
+    for (header_policy, header_format, request_id) in cases {
+        let spans = collect_spans(|| {
+            let service = HttpLayer::builder()
+                .server()
+                .level(Level::INFO)
+                .header_policy(header_policy.clone())
+                .header_format(header_format)
+                .build()
+                .layer(ServiceFn(|_| {
+                    Response::builder()
+                        .header("content-type", "application/json")
+                        .header("x-request-id", "42")
+                        .body(String::new())
+                        .unwrap()
+                }));
+            let request = Request::get("/").body(String::new()).unwrap();
+            call(service, request).unwrap();
+        });
+
+        let span = find_span(&spans, SpanKind::Server);
+        let case = format!("{:?} {:?}", header_policy, header_format);
+        assert_eq!(
+            attribute(span, "http.response.header.content-type"),
+            Some(strings(&["application/json"])),
+            "{}",
+            case
+        );
+        assert_eq!(
+            attribute(span, "http.response.header.x-request-id"),
+            request_id,
+            "{}",
+            case
+        );
+        if let (HeaderPolicy::All, HeaderFormat::Json) = (&header_policy, header_format) {
+            let headers = attribute(span, "http.response.headers").expect("headers not found");
+            assert!(headers.as_str().contains("\"x-request-id\""));
+        }
+    }
+}
+
+#[test]
+fn layer_from_config() {
+    use tower_otel::trace::{HttpTraceConfig, SpanKind as TraceKind};
+
+    let spans = collect_spans(|| {
+        let mut config = HttpTraceConfig::default();
+        config.kind = TraceKind::Client;
+        config.url_query = false;
+        let service =
+            HttpLayer::from_config(config).layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("http://example.com/?token=secret")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(
+        attribute(span, "url.full"),
+        Some("http://example.com/".into())
+    );
+    assert_eq!(attribute(span, "url.query"), None);
+}
+
+#[test]
+fn layer_from_builder() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .client()
+            .url_query(false)
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("http://example.com/?token=secret")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(
+        attribute(span, "url.full"),
+        Some("http://example.com/".into())
+    );
+    assert_eq!(attribute(span, "url.query"), None);
+}
+
+#[test]
+fn server_span_records_sampled_attribute() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .sampled_attribute(true)
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "otel.sampled"), Some(true.into()));
+}
+
+#[test]
+fn proxy_span_relays_the_context() {
+    use tower_otel::trace::ProxyLayer;
+
+    let spans = collect_spans(|| {
+        let server = HttpLayer::server(Level::INFO);
+        let client = HttpLayer::builder()
+            .client()
+            .level(Level::INFO)
+            .propagator(TraceContextPropagator::new())
+            .build();
+        let service =
+            ProxyLayer::from_layers(server, client).layer(ServiceFn(|request: Request<String>| {
+                assert!(request.headers().contains_key("traceparent"));
+                Response::new(String::new())
+            }));
+        let request = Request::get("http://example.com/")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let server = find_span(&spans, SpanKind::Server);
+    let client = find_span(&spans, SpanKind::Client);
+    assert_eq!(
+        client.span_context.trace_id(),
+        server.span_context.trace_id()
+    );
+    assert_eq!(client.parent_span_id, server.span_context.span_id());
+}
+
+#[test]
+fn server_span_records_response_error() {
+    use tower_otel::trace::extensions::ResponseError;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::server(Level::INFO).layer(ServiceFn(|_| {
+            let err = std::fmt::Error;
+            let mut response = Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(String::new())
+                .unwrap();
+            response.extensions_mut().insert(ResponseError::new(&err));
+            response
+        }));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "error.message"),
+        Some("an error occurred when formatting an argument".into())
+    );
+    assert_eq!(
+        attribute(span, "error.type"),
+        Some("core::fmt::Error".into())
+    );
+    assert!(matches!(span.status, Status::Error { .. }));
+}
+
+#[test]
+fn server_span_truncates_header_values() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .max_header_value_len(4)
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/")
+            .header("cookie", "session=0123456789")
+            .header("accept", "*/*")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "http.request.header.cookie"),
+        Some(strings(&["sess..."]))
+    );
+    assert_eq!(
+        attribute(span, "http.request.header.accept"),
+        Some(strings(&["*/*"]))
+    );
+}
+
+#[test]
+fn server_span_records_forwarded_client() {
+    let spans = collect_spans(|| {
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/")
+            .header("forwarded", "for=\"[2001:db8:cafe::17]:4711\";proto=https")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "client.address"),
+        Some("2001:db8:cafe::17".into())
+    );
+    assert_eq!(attribute(span, "client.port"), Some(4711.into()));
+}
+
+#[test]
+fn server_span_records_compression_ratio() {
+    use common::consume;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .response_body_size(true)
+            .build()
+            .layer(ServiceFn(|_| {
+                Response::builder()
+                    .header("x-uncompressed-length", "40")
+                    .body(String::from("compressed"))
+                    .unwrap()
+            }));
+        let request = Request::get("/").body(String::new()).unwrap();
+        let response = call(service, request).unwrap();
+        consume(response.into_body());
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "http.response.body.size"), Some(10.into()));
+    assert_eq!(
+        attribute(span, "http.response.body.uncompressed_size"),
+        Some(40.into())
+    );
+    assert_eq!(
+        attribute(span, "http.response.compression_ratio"),
+        Some(4.0.into())
+    );
+}
+
+#[test]
+fn server_span_emits_request_event() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .request_event(true)
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    let event = span.events.iter().next().expect("event not found");
+    assert_eq!(event.name, "http.request.start");
+    let offset_ns = event
+        .attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == "offset_ns")
+        .expect("offset not found");
+    assert!(matches!(offset_ns.value, opentelemetry::Value::I64(offset_ns) if offset_ns >= 0));
+}
+
+#[test]
+fn server_span_emits_error_event() {
+    use common::Failing;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .error_events(true)
+            .build()
+            .layer(Failing("connection reset"));
+        let request = Request::get("/").body(String::new()).unwrap();
+        assert!(call(service, request).is_err());
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    let event = span.events.iter().next().expect("event not found");
+    assert_eq!(event.name, "request failed");
+    assert!(event
+        .attributes
+        .iter()
+        .any(|attribute| attribute.key.as_str() == "error.message"
+            && attribute.value == "connection reset".into()));
+}
+
+#[test]
+fn client_span_keeps_existing_traceparent() {
+    use tower_otel::trace::InjectMode;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    collect_spans(|| {
+        let service = HttpLayer::builder()
+            .client()
+            .level(Level::INFO)
+            .inject_mode(InjectMode::IfAbsent)
+            .propagator(TraceContextPropagator::new())
+            .build()
+            .layer(ServiceFn(|request: Request<String>| {
+                assert_eq!(request.headers()["traceparent"], TRACEPARENT);
+                Response::new(String::new())
+            }));
+        let request = Request::get("http://example.com/")
+            .header("traceparent", TRACEPARENT)
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+}
+
+fn get_user() -> Response<String> {
+    let mut response = Response::new(String::new());
+    response
+        .extensions_mut()
+        .insert(tower_otel::code_function!());
+    response
+}
+
+#[test]
+fn server_span_records_code_function() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::server(Level::INFO).layer(ServiceFn(|_| get_user()));
+        let request = Request::get("/users/1").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "code.function.name"),
+        Some("http::get_user".into())
+    );
+}
+
+#[test]
+fn server_span_extracts_context_with_layer_propagator() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .propagator(TraceContextPropagator::new())
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        span.span_context.trace_id().to_string(),
+        "4bf92f3577b34da6a3ce929d0e0e4736"
+    );
+    assert_eq!(span.parent_span_id.to_string(), "00f067aa0ba902b7");
+}
+
+#[cfg(feature = "tower")]
+#[test]
+fn server_span_records_canonical_error_type() {
+    use tower::{load_shed::error::Overloaded, timeout::error::Elapsed};
+
+    use common::FailingWith;
+
+    let spans = collect_spans(|| {
+        let service =
+            HttpLayer::server(Level::INFO).layer(FailingWith(|| Box::new(Elapsed::new()) as _));
+        let request = Request::get("/").body(String::new()).unwrap();
+        assert!(call(service, request).is_err());
+
+        let service =
+            HttpLayer::client(Level::INFO).layer(FailingWith(|| Box::new(Overloaded::new()) as _));
+        let request = Request::get("http://example.com/")
+            .body(String::new())
+            .unwrap();
+        assert!(call(service, request).is_err());
+    });
+
+    let server = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(server, "error.type"), Some("timeout".into()));
+    assert_eq!(
+        attribute(server, "error.message"),
+        Some("request timed out".into())
+    );
+    let client = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(client, "error.type"), Some("overloaded".into()));
+}
+
+#[test]
+fn server_span_records_headers_as_json() {
+    use tower_otel::trace::HeaderFormat;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .header_format(HeaderFormat::Json)
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/")
+            .header("accept", "text/html")
+            .header("x-tag", "a\"b")
+            .header("x-tag", "c")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "http.request.headers"),
+        Some(r#"{"accept":"text/html","x-tag":["a\"b","c"]}"#.into())
+    );
+    assert_eq!(attribute(span, "http.request.header.accept"), None);
+}
+
+#[test]
+fn spans_are_filtered_by_kind_target() {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+
+    use common::{collect_filtered_spans, Failing};
+
+    let targets = Targets::new()
+        .with_default(LevelFilter::TRACE)
+        .with_target("tower_otel::trace::http::client", LevelFilter::OFF);
+    let spans = collect_filtered_spans(targets, || {
+        let client = HttpLayer::builder()
+            .client()
+            .request_event(true)
+            .error_events(true)
+            .build()
+            .layer(Failing("connection reset"));
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(move |request: Request<String>| {
+                let request = Request::get("http://example.com/")
+                    .body(request.into_body())
+                    .unwrap();
+                assert!(call(client.clone(), request).is_err());
+                Response::new(String::new())
+            }));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].span_kind, SpanKind::Server);
+    // the events of the client are filtered out with its span
+    assert_eq!(spans[0].events.len(), 0);
+}
+
+#[test]
+fn filtered_span_does_not_insert_context_extension() {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+
+    use common::collect_filtered_spans;
+
+    let targets = Targets::new()
+        .with_default(LevelFilter::TRACE)
+        .with_target("tower_otel::trace::http::server", LevelFilter::OFF);
+    let spans = collect_filtered_spans(targets, || {
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|request: Request<String>| {
+                assert!(request
+                    .extensions()
+                    .get::<opentelemetry::Context>()
+                    .is_none());
+                Response::new(String::new())
+            }));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    assert!(spans.is_empty());
+}
+
+#[test]
+fn server_span_records_server_address_from_host() {
+    let spans = collect_spans(|| {
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::get("/")
+            .header("host", "example.com:8080")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "server.address"),
+        Some("example.com".into())
+    );
+    assert_eq!(attribute(span, "server.port"), Some(8080.into()));
+}
+
+#[test]
+fn server_span_status_description_is_error_message() {
+    use common::Failing;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::server(Level::INFO).layer(Failing("connection reset"));
+        let request = Request::get("/").body(String::new()).unwrap();
+        assert!(call(service, request).is_err());
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        span.status,
+        Status::Error {
+            description: "connection reset".into()
+        }
+    );
+    assert_eq!(attribute(span, "otel.status_message"), None);
+    assert_eq!(attribute(span, "error.type"), Some("_OTHER".into()));
+}
+
+#[test]
+fn server_span_status_description_is_response_error() {
+    use tower_otel::trace::extensions::ResponseError;
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::server(Level::INFO).layer(ServiceFn(|_| {
+            let mut response = Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(String::new())
+                .unwrap();
+            response
+                .extensions_mut()
+                .insert(ResponseError::new(&std::fmt::Error));
+            response
+        }));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        span.status,
+        Status::Error {
+            description: "an error occurred when formatting an argument".into()
+        }
+    );
+}
+
+#[test]
+fn layer_wraps_non_clone_service() {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+        task::{Context, Poll},
+    };
+
+    use tower_service::Service;
+
+    /// Service owning a resource that cannot be cloned.
+    struct NotClone(std::sync::Mutex<u32>);
+
+    impl Service<Request<String>> for NotClone {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: Request<String>) -> Self::Future {
+            *self.0.lock().unwrap() += 1;
+            ready(Ok(Response::new(String::new())))
+        }
+    }
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::server(Level::INFO).layer(NotClone(Default::default()));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        attribute(span, "http.response.status_code"),
+        Some(200.into())
+    );
+}
+
+/// Service that is ready only from the second poll.
+#[derive(Clone)]
+struct ReadyOnSecondPoll(bool);
+
+impl tower_service::Service<Request<String>> for ReadyOnSecondPoll {
+    type Response = Response<String>;
+    type Error = std::convert::Infallible;
+    type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        if std::mem::replace(&mut self.0, true) {
+            std::task::Poll::Ready(Ok(()))
+        } else {
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        }
+    }
+
+    fn call(&mut self, _request: Request<String>) -> Self::Future {
+        std::future::ready(Ok(Response::new(String::new())))
+    }
+}
+
+#[test]
+fn client_span_records_poll_ready_duration() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::client(Level::INFO).layer(ReadyOnSecondPoll(false));
+        let request = Request::get("http://example.com/")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+
+        let service = HttpLayer::server(Level::INFO).layer(ReadyOnSecondPoll(false));
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service, request).unwrap();
+    });
+
+    let client = find_span(&spans, SpanKind::Client);
+    assert!(attribute(client, "http.client.poll_ready.duration").is_some());
+    let server = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(server, "http.client.poll_ready.duration"), None);
+}
+
+#[test]
+fn cloned_client_does_not_inherit_poll_ready_duration() {
+    use std::task::{Context, Waker};
+
+    use tower_service::Service;
+
+    let spans = collect_spans(|| {
+        let mut service = HttpLayer::client(Level::INFO).layer(ReadyOnSecondPoll(false));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(service.poll_ready(&mut cx).is_pending());
+
+        let request = Request::get("http://example.com/")
+            .body(String::new())
+            .unwrap();
+        call(service.clone(), request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Client);
+    assert_eq!(attribute(span, "http.client.poll_ready.duration"), None);
+}
+
+#[test]
+fn server_span_records_original_method() {
+    let spans = collect_spans(|| {
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|_| Response::new(String::new())));
+        for method in ["get", "FOO"] {
+            let request = Request::builder()
+                .method(method)
+                .uri("/")
+                .body(String::new())
+                .unwrap();
+            call(service.clone(), request).unwrap();
+        }
+    });
+
+    assert_eq!(spans.len(), 2);
+    for (span, method) in spans.iter().zip(["get", "FOO"]) {
+        assert_eq!(
+            attribute(span, "http.request.method"),
+            Some("_OTHER".into())
+        );
+        assert_eq!(
+            attribute(span, "http.request.method_original"),
+            Some(method.into())
+        );
+        assert_eq!(span.name, "HTTP");
+    }
+}
+
+#[test]
+fn server_span_honors_sampled_flag() {
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .propagator(TraceContextPropagator::new())
+            .build()
+            .layer(ServiceFn(|_| Response::new(String::new())));
+        for traceparent in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00",
+        ] {
+            let request = Request::get("/")
+                .header("traceparent", traceparent)
+                .body(String::new())
+                .unwrap();
+            call(service.clone(), request).unwrap();
+        }
+    });
+
+    // the span of the unsampled parent is not exported
+    assert_eq!(spans.len(), 1);
+    let span = &spans[0];
+    assert_eq!(
+        span.span_context.trace_id().to_string(),
+        "4bf92f3577b34da6a3ce929d0e0e4736"
+    );
+    assert_eq!(span.parent_span_id.to_string(), "00f067aa0ba902b7");
+    assert!(span.span_context.is_sampled());
+}
+
+#[test]
+fn composite_propagator_round_trips_b3() {
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry_sdk::propagation::TextMapCompositePropagator;
+
+    use common::B3Propagator;
+
+    fn propagator() -> TextMapCompositePropagator {
+        let propagators: Vec<Box<dyn TextMapPropagator + Send + Sync>> = vec![
+            Box::new(TraceContextPropagator::new()),
+            Box::new(B3Propagator::new()),
+        ];
+        TextMapCompositePropagator::new(propagators)
+    }
+
+    let spans = collect_spans(|| {
+        let client = HttpLayer::builder()
+            .client()
+            .level(Level::INFO)
+            .propagator(propagator())
+            .build()
+            .layer(ServiceFn(|request: Request<String>| {
+                let headers = request.headers();
+                assert!(headers.contains_key("traceparent"));
+                assert!(headers.contains_key("x-b3-traceid"));
+                assert!(headers.contains_key("x-b3-spanid"));
+                let trace_id = headers["x-b3-traceid"].to_str().unwrap();
+                assert_eq!(trace_id, "80f198ee56343ba864fe8b2a57d3eff7");
+                Response::new(String::new())
+            }));
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .propagator(propagator())
+            .build()
+            .layer(ServiceFn(move |_| {
+                let request = Request::get("http://example.com/")
+                    .body(String::new())
+                    .unwrap();
+                call(client.clone(), request).unwrap()
+            }));
+        let request = Request::get("/")
+            .header("x-b3-traceid", "80f198ee56343ba864fe8b2a57d3eff7")
+            .header("x-b3-spanid", "e457b5a2e4d86bd1")
+            .header("x-b3-sampled", "1")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let server = find_span(&spans, SpanKind::Server);
+    assert_eq!(
+        server.span_context.trace_id().to_string(),
+        "80f198ee56343ba864fe8b2a57d3eff7"
+    );
+    assert_eq!(server.parent_span_id.to_string(), "e457b5a2e4d86bd1");
+    let client = find_span(&spans, SpanKind::Client);
+    assert_eq!(client.parent_span_id, server.span_context.span_id());
+}
+
+#[test]
+fn server_span_links_contexts_of_link_header() {
+    use http::HeaderName;
+
+    use common::B3Propagator;
+
+    let link_header = HeaderName::from_static("link-traceparent");
+    let spans = collect_spans(|| {
+        // the global propagator is the no-op one, and the layer propagates only b3
+        let layers = [
+            HttpLayer::builder()
+                .link_header(link_header.clone())
+                .build(),
+            HttpLayer::builder()
+                .link_header(link_header.clone())
+                .propagator(B3Propagator::new())
+                .build(),
+        ];
+        for layer in layers {
+            let service = layer.layer(ServiceFn(|_| Response::new(String::new())));
+            let request = Request::get("/")
+                .header(
+                    &link_header,
+                    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                )
+                .header(
+                    &link_header,
+                    "00-80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-00",
+                )
+                .header(&link_header, "invalid")
+                .body(String::new())
+                .unwrap();
+            call(service, request).unwrap();
+        }
+    });
+
+    assert_eq!(spans.len(), 2);
+    for span in &spans {
+        let links = span
+            .links
+            .iter()
+            .map(|link| {
+                let span_context = &link.span_context;
+                (
+                    span_context.trace_id().to_string(),
+                    span_context.span_id().to_string(),
+                    span_context.is_sampled(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [
+                (
+                    "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+                    "00f067aa0ba902b7".to_owned(),
+                    true
+                ),
+                (
+                    "80f198ee56343ba864fe8b2a57d3eff7".to_owned(),
+                    "e457b5a2e4d86bd1".to_owned(),
+                    false
+                ),
+            ]
+        );
+    }
+}
+
+#[cfg(feature = "axum")]
+#[test]
+fn server_span_records_axum_matched_path() {
+    use axum::{body::Body, routing::get, Router};
+
+    let spans = collect_spans(|| {
+        let router = Router::new()
+            .route("/users/:id", get(|| async { "user" }))
+            .layer(HttpLayer::server(Level::INFO));
+        let request = Request::get("/users/42").body(Body::empty()).unwrap();
+        call(router, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "http.route"), Some("/users/:id".into()));
+    assert_eq!(attribute(span, "url.template"), Some("/users/:id".into()));
+    assert_eq!(attribute(span, "url.path"), Some("/users/42".into()));
+    assert_eq!(span.name, "GET /users/:id");
+}
+
+#[test]
+fn server_span_records_url_scheme() {
+    use tower_otel::trace::extensions::TlsInfo;
+
+    let spans = collect_spans(|| {
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|_| Response::new(String::new())));
+
+        // HTTP/2 request, the `:scheme` pseudo-header is exposed in the URI
+        let request = Request::get("https://example.com/")
+            .version(http::Version::HTTP_2)
+            .body(String::new())
+            .unwrap();
+        call(service.clone(), request).unwrap();
+
+        let request = Request::get("/").body(String::new()).unwrap();
+        call(service.clone(), request).unwrap();
+
+        let request = Request::get("/")
+            .extension(TlsInfo::default())
+            .body(String::new())
+            .unwrap();
+        call(service.clone(), request).unwrap();
+
+        // HTTP/1 requests behind a proxy
+        let request = Request::get("/")
+            .header("forwarded", "for=192.0.2.60;proto=https")
+            .body(String::new())
+            .unwrap();
+        call(service.clone(), request).unwrap();
+
+        let request = Request::get("/")
+            .header("x-forwarded-proto", "https")
+            .body(String::new())
+            .unwrap();
+        call(service.clone(), request).unwrap();
+
+        // `Forwarded` is preferred to `X-Forwarded-Proto`
+        let request = Request::get("/")
+            .header("forwarded", "proto=http")
+            .header("x-forwarded-proto", "https")
+            .body(String::new())
+            .unwrap();
+        call(service.clone(), request).unwrap();
+
+        // the scheme of the URI is preferred to the headers
+        let request = Request::get("http://example.com/")
+            .version(http::Version::HTTP_2)
+            .header("forwarded", "proto=https")
+            .header("x-forwarded-proto", "https")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let schemes = spans
+        .iter()
+        .map(|span| attribute(span, "url.scheme"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        schemes,
+        [
+            Some("https".into()),
+            Some("http".into()),
+            Some("https".into()),
+            Some("https".into()),
+            Some("https".into()),
+            Some("http".into()),
+            Some("http".into()),
+        ]
+    );
+}
+
+#[test]
+fn server_span_records_request_size_from_size_hint() {
+    let spans = collect_spans(|| {
+        let service =
+            HttpLayer::server(Level::INFO).layer(ServiceFn(|_| Response::new(String::new())));
+        let request = Request::post("/")
+            .version(http::Version::HTTP_2)
+            .body(String::from("hello"))
+            .unwrap();
+        assert!(!request.headers().contains_key("content-length"));
+        call(service, request).unwrap();
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "http.request.body.size"), Some(5.into()));
+    assert_eq!(
+        attribute(span, "network.protocol.version"),
+        Some("2".into())
+    );
+}
+
+#[test]
+fn operation_span_is_parent_of_the_attempts() {
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tower_service::Service;
+
+    /// Service that sends each request twice, as a retry layer would do.
+    struct TwoAttempts<S>(S);
+
+    impl<S> Service<Request<String>> for TwoAttempts<S>
+    where
+        S: Service<Request<String>> + Clone + 'static,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>>>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.0.poll_ready(cx)
+        }
+
+        fn call(&mut self, request: Request<String>) -> Self::Future {
+            let mut inner = self.0.clone();
+            Box::pin(async move {
+                let mut retry = Request::new(request.body().clone());
+                *retry.uri_mut() = request.uri().clone();
+                *retry.extensions_mut() = request.extensions().clone();
+                inner.call(request).await?;
+                inner.call(retry).await
+            })
+        }
+    }
+
+    let spans = collect_spans(|| {
+        let attempt = HttpLayer::client(Level::INFO)
+            .layer(ServiceFn(|_: Request<String>| Response::new(String::new())));
+        let service = HttpLayer::builder()
+            .client()
+            .operation_span(true)
+            .build()
+            .layer(TwoAttempts(attempt));
+        let request = Request::get("http://example.com/")
+            .body(String::new())
+            .unwrap();
+        call(service, request).unwrap();
+    });
+
+    let operation = find_span(&spans, SpanKind::Internal);
+    let attempts = spans
+        .iter()
+        .filter(|span| span.span_kind == SpanKind::Client)
+        .collect::<Vec<_>>();
+    assert_eq!(attempts.len(), 2);
+    for attempt in &attempts {
+        assert_eq!(
+            attempt.span_context.trace_id(),
+            operation.span_context.trace_id()
+        );
+        assert_eq!(attempt.parent_span_id, operation.span_context.span_id());
+    }
+    assert_eq!(attribute(attempts[0], "http.request.resend_count"), None);
+    assert_eq!(
+        attribute(attempts[1], "http.request.resend_count"),
+        Some(1.into())
+    );
+}
+
+#[test]
+fn server_span_records_response_trailers() {
+    use http::{HeaderMap, HeaderName};
+    use tower_otel::trace::{HeaderFormat, HeaderPolicy};
+
+    use common::{consume, FramesBody};
+
+    let cases = [
+        (HeaderPolicy::All, HeaderFormat::Attributes),
+        (
+            HeaderPolicy::Deny(vec![HeaderName::from_static("x-secret")]),
+            HeaderFormat::Attributes,
+        ),
+        (HeaderPolicy::All, HeaderFormat::Json),
+    ];
+    for (header_policy, header_format) in cases {
+        let spans = collect_spans(|| {
+            let service = HttpLayer::builder()
+                .server()
+                .level(Level::INFO)
+                .response_trailers(true)
+                .header_policy(header_policy.clone())
+                .header_format(header_format)
+                .build()
+                .layer(ServiceFn(|_| {
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert("x-checksum", "abc".parse().unwrap());
+                    trailers.insert("x-secret", "42".parse().unwrap());
+                    Response::new(FramesBody::new(&[b"hello"], trailers))
+                }));
+            let request = Request::get("/").body(String::new()).unwrap();
+            let response = call(service, request).unwrap();
+            consume(response.into_body());
+        });
+
+        let span = find_span(&spans, SpanKind::Server);
+        let case = format!("{:?} {:?}", header_policy, header_format);
+        match header_format {
+            HeaderFormat::Attributes => {
+                assert_eq!(
+                    attribute(span, "http.response.trailer.x-checksum"),
+                    Some(strings(&["abc"])),
+                    "{}",
+                    case
+                );
+                let secret = match header_policy {
+                    HeaderPolicy::Deny(_) => None,
+                    _ => Some(strings(&["42"])),
+                };
+                assert_eq!(
+                    attribute(span, "http.response.trailer.x-secret"),
+                    secret,
+                    "{}",
+                    case
+                );
+                assert_eq!(attribute(span, "http.response.trailers"), None, "{}", case);
+            }
+            HeaderFormat::Json => {
+                assert_eq!(
+                    attribute(span, "http.response.trailer.x-checksum"),
+                    None,
+                    "{}",
+                    case
+                );
+                let trailers =
+                    attribute(span, "http.response.trailers").expect("trailers not found");
+                assert!(trailers.as_str().contains("\"x-checksum\""), "{}", case);
+            }
+        }
+    }
+}
+
+#[test]
+fn server_span_records_panic() {
+    use std::{
+        convert::Infallible,
+        future::Future,
+        panic::{self, AssertUnwindSafe},
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tower_service::Service;
+
+    /// Service whose responses panic when polled.
+    struct Panicking;
+
+    impl Service<Request<String>> for Panicking {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Response<String>, Infallible>>>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: Request<String>) -> Self::Future {
+            Box::pin(async { panic!("handler panicked") })
+        }
+    }
+
+    let spans = collect_spans(|| {
+        let service = HttpLayer::builder()
+            .server()
+            .level(Level::INFO)
+            .panic_capture(true)
+            .build()
+            .layer(Panicking);
+        let request = Request::get("/").body(String::new()).unwrap();
+        let payload = match panic::catch_unwind(AssertUnwindSafe(|| call(service, request))) {
+            Ok(_) => panic!("the panic is not re-raised"),
+            Err(payload) => payload,
+        };
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"handler panicked"));
+    });
+
+    let span = find_span(&spans, SpanKind::Server);
+    assert_eq!(attribute(span, "exception.type"), Some("panic".into()));
+    assert_eq!(
+        attribute(span, "exception.message"),
+        Some("handler panicked".into())
+    );
+    assert!(matches!(span.status, Status::Error { .. }));
+}